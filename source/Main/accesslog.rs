@@ -0,0 +1,304 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+
+//! The access log, with a line for every request, in a format log analysers understand. Unlike
+//! the request lines in the terminal, it holds no colours or padding.
+
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::ServiceResponse;
+use actix_web::http::{header, Method};
+use actix_web::web::Data;
+use actix_web::{HttpMessage, HttpRequest};
+use chrono::{FixedOffset, SecondsFormat, Utc};
+use log::error;
+use serde_json::json;
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use crate::config::AccessLogFormat;
+use crate::{LockCallback, ServerContext};
+
+/// Where a response came from.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) enum Source {
+    /// Served from the cache.
+    Cache,
+    /// Made by Cynthia for this request, like a freshly rendered page or a redirect.
+    #[default]
+    Generated,
+    /// Read from disk, like an asset that wasn't cached (yet).
+    File,
+    /// Answered by a plugin.
+    Plugin,
+}
+
+impl Source {
+    fn name(&self) -> &'static str {
+        match self {
+            Source::Cache => "cache",
+            Source::Generated => "generated",
+            Source::File => "file",
+            Source::Plugin => "plugin",
+        }
+    }
+}
+
+/// Notes where the response to `req` came from. Responses without a note were generated.
+pub(crate) fn mark(req: &HttpRequest, source: Source) {
+    req.extensions_mut().insert(source);
+}
+
+/// How often buffered lines are flushed to the access log.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The sending end of the access log, kept in [`ServerContext`].
+#[derive(Debug)]
+pub(crate) struct AccessLogSink {
+    lines: Sender<String>,
+    format: AccessLogFormat,
+    writer: JoinHandle<()>,
+}
+
+impl AccessLogSink {
+    /// Opens the access log at `path`, appending to it if it exists, and starts writing the lines
+    /// it is sent to it in the background.
+    pub(crate) fn open(path: &Path, format: AccessLogFormat) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (sender, receiver) = mpsc::channel(1024);
+        Ok(Self {
+            lines: sender,
+            format,
+            writer: tokio::spawn(writer(BufWriter::new(File::from_std(file)), receiver)),
+        })
+    }
+    /// Stops taking lines, and waits until the writer has written and flushed the ones it got.
+    pub(crate) async fn close(self) {
+        drop(self.lines);
+        if let Err(e) = self.writer.await {
+            error!("The access log writer stopped unexpectedly: {e}");
+        }
+    }
+}
+
+/// Writes the lines sent to the access log, flushing them every [`FLUSH_INTERVAL`] and once more
+/// when the sink is closed.
+async fn writer(mut file: BufWriter<File>, mut lines: Receiver<String>) {
+    let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+    loop {
+        let result = tokio::select! {
+            line = lines.recv() => match line {
+                Some(line) => file.write_all(format!("{line}\n").as_bytes()).await,
+                None => break,
+            },
+            _ = flush.tick() => file.flush().await,
+        };
+        if let Err(e) = result {
+            error!("Could not write to the access log: {e}");
+        }
+    }
+    let _ = file.flush().await;
+}
+
+/// Writes the line for a response of the main server, if the access log is enabled. Used as a
+/// middleware, so every route is logged the same way.
+pub(crate) async fn write<B: MessageBody>(response: &ServiceResponse<B>, duration: Duration) {
+    let req = response.request();
+    let Some(server_context_mutex) = req.app_data::<Data<Arc<Mutex<ServerContext>>>>() else {
+        return;
+    };
+    let now = Utc::now();
+    let Some((offset, format, lines)) = server_context_mutex
+        .lock_callback(|a| {
+            let sink = a.access_log.as_ref()?;
+            let offset = a
+                .config
+                .site
+                .utc_offset(now.timestamp())
+                .unwrap_or(FixedOffset::east_opt(0).unwrap());
+            Some((offset, sink.format, sink.lines.clone()))
+        })
+        .await
+    else {
+        return;
+    };
+    let source = req
+        .extensions()
+        .get::<Source>()
+        .copied()
+        .unwrap_or_default();
+    // Responses to HEAD requests never have a body, whatever their size would be.
+    let size = match response.response().body().size() {
+        _ if req.method() == Method::HEAD => Some(0),
+        BodySize::Sized(n) => Some(n),
+        BodySize::None => Some(0),
+        BodySize::Stream => None,
+    };
+    let entry = Entry {
+        ip: req
+            .connection_info()
+            .realip_remote_addr()
+            .unwrap_or("-")
+            .to_string(),
+        method: req.method().as_str(),
+        uri: req
+            .uri()
+            .path_and_query()
+            .map(|p| p.as_str())
+            .unwrap_or("/"),
+        protocol: format!("{:?}", req.version()),
+        status: response.status().as_u16(),
+        size,
+        referer: header_value(req, header::REFERER),
+        user_agent: header_value(req, header::USER_AGENT),
+        duration,
+        source,
+    };
+    let now = now.with_timezone(&offset);
+    let line = match format {
+        AccessLogFormat::Combined => entry.combined(now),
+        AccessLogFormat::Json => entry.json(now),
+    };
+    if lines.send(line).await.is_err() {
+        error!("Could not write to the access log: the writer stopped.");
+    }
+}
+
+struct Entry<'a> {
+    ip: String,
+    method: &'a str,
+    uri: &'a str,
+    protocol: String,
+    status: u16,
+    size: Option<u64>,
+    referer: Option<&'a str>,
+    user_agent: Option<&'a str>,
+    duration: Duration,
+    source: Source,
+}
+
+impl Entry<'_> {
+    /// `ip - - [time] "request" status size "referer" "user-agent" duration source`, with the
+    /// duration in microseconds.
+    fn combined(&self, now: chrono::DateTime<FixedOffset>) -> String {
+        format!(
+            "{} - - [{}] \"{}\" {} {} \"{}\" \"{}\" {} {}",
+            self.ip,
+            now.format("%d/%b/%Y:%H:%M:%S %z"),
+            quote(&format!("{} {} {}", self.method, self.uri, self.protocol)),
+            self.status,
+            // The Combined Log Format writes empty bodies as `-` too.
+            match self.size {
+                Some(n) if n > 0 => n.to_string(),
+                _ => String::from("-"),
+            },
+            quote(self.referer.unwrap_or("-")),
+            quote(self.user_agent.unwrap_or("-")),
+            self.duration.as_micros(),
+            self.source.name()
+        )
+    }
+    fn json(&self, now: chrono::DateTime<FixedOffset>) -> String {
+        json!({
+            "time": now.to_rfc3339_opts(SecondsFormat::Millis, true),
+            "ip": self.ip,
+            "method": self.method,
+            "uri": self.uri,
+            "protocol": self.protocol,
+            "status": self.status,
+            "size": self.size,
+            "referer": self.referer,
+            "user_agent": self.user_agent,
+            "duration_ms": self.duration.as_secs_f64() * 1000.0,
+            "source": self.source.name(),
+        })
+        .to_string()
+    }
+}
+
+fn header_value(req: &HttpRequest, name: header::HeaderName) -> Option<&str> {
+    req.headers().get(name).and_then(|v| v.to_str().ok())
+}
+
+/// Escapes a value for between the quotes of a Combined Log Format line. Control characters are
+/// escaped like Apache does, so a header can't break a line in two.
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\x{:02x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn entry() -> Entry<'static> {
+        Entry {
+            ip: String::from("203.0.113.7"),
+            method: "GET",
+            uri: "/posts/hello?page=2",
+            protocol: String::from("HTTP/1.1"),
+            status: 200,
+            size: Some(1234),
+            referer: Some("https://example.com/"),
+            user_agent: Some("curl/8.0"),
+            duration: Duration::from_micros(1500),
+            source: Source::Cache,
+        }
+    }
+
+    fn now() -> chrono::DateTime<FixedOffset> {
+        FixedOffset::east_opt(2 * 3600)
+            .unwrap()
+            .with_ymd_and_hms(2024, 3, 9, 14, 5, 7)
+            .unwrap()
+    }
+
+    #[test]
+    fn combined() {
+        assert_eq!(
+            entry().combined(now()),
+            "203.0.113.7 - - [09/Mar/2024:14:05:07 +0200] \"GET /posts/hello?page=2 HTTP/1.1\" 200 1234 \"https://example.com/\" \"curl/8.0\" 1500 cache"
+        );
+        let entry = Entry {
+            size: Some(0),
+            referer: None,
+            user_agent: Some("Evil \"agent\"\n127.0.0.1 - - [x] \"GET / HTTP/1.1\" 200 -"),
+            ..entry()
+        };
+        let line = entry.combined(now());
+        assert!(!line.contains('\n'));
+        assert!(line.contains(" 200 - \"-\" \"Evil \\\"agent\\\"\\n127.0.0.1 - - [x] \\\"GET / HTTP/1.1\\\" 200 -\" 1500 cache"));
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quote("plain text"), "plain text");
+        assert_eq!(quote(r#"say "hi""#), r#"say \"hi\""#);
+        assert_eq!(quote(r"C:\path"), r"C:\\path");
+        assert_eq!(quote("a\tb\r\nc"), r"a\tb\r\nc");
+        assert_eq!(quote("bell\u{7}del\u{7f}"), r"bell\x07del\x7f");
+        assert_eq!(quote("naïve ✓"), "naïve ✓");
+    }
+}
@@ -18,15 +18,35 @@ pub(super) struct CynthiaCacheObject {
     id: String,
     content: Vec<u8>,
     timestamp: (u64, u64),
+    /// The `Content-Type` to serve this object with, if it's served directly.
+    content_type: Option<String>,
+    /// Compressed versions of `content`, by content coding (like `br` or `gzip`). These expire
+    /// together with the object itself.
+    variants: Vec<(String, Vec<u8>)>,
 }
 #[derive(Debug, Clone)]
-pub(crate) struct CynthiaCacheExtraction(pub(crate) Vec<u8>, #[allow(dead_code)] pub(crate) u64);
+pub(crate) struct CynthiaCacheExtraction(
+    pub(crate) Vec<u8>,
+    #[allow(dead_code)] pub(crate) u64,
+    pub(crate) Option<String>,
+);
 impl ServerContext {
     pub(crate) fn store_cache(
         &mut self,
         id: &str,
         contents: &[u8],
         max_age: u64,
+    ) -> Result<(), String> {
+        self.store_cache_with_type(id, contents, None, max_age)
+    }
+    /// Like [`ServerContext::store_cache`], but also keeps the content type, so cache hits can be
+    /// served with the same `Content-Type` as the original response.
+    pub(crate) fn store_cache_with_type(
+        &mut self,
+        id: &str,
+        contents: &[u8],
+        content_type: Option<&str>,
+        max_age: u64,
     ) -> Result<(), String> {
         self.evaluate_cache();
         let now = match SystemTime::now().duration_since(UNIX_EPOCH) {
@@ -39,6 +59,8 @@ impl ServerContext {
             id: id.to_string(),
             content: Vec::from(contents),
             timestamp: (now, now + max_age),
+            content_type: content_type.map(String::from),
+            variants: vec![],
         };
         self.cache.push(cache);
         Ok(())
@@ -58,6 +80,8 @@ impl ServerContext {
             id: id.to_string(),
             content: Vec::from(contents),
             timestamp: (now, now + max_age),
+            content_type: None,
+            variants: vec![],
         };
         self.cache.push(cache);
         Ok(())
@@ -68,22 +92,52 @@ impl ServerContext {
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_secs();
-        let object = self
+        let Some(object) = self
             .cache
             .iter()
             .find(|&x| {
                 trace!("Cache check: {} - {:#?}", id, x.id);
                 x.id == id
-            })?
-            .clone();
+            })
+            .cloned()
+        else {
+            self.metrics.count_cache(false);
+            return None;
+        };
         trace!("Cache hit: {}", id);
-        if max_age == 0 || ((now - object.timestamp.0) < max_age) {
-            Some(CynthiaCacheExtraction(object.content, object.timestamp.0))
+        let fresh = max_age == 0 || ((now - object.timestamp.0) < max_age);
+        self.metrics.count_cache(fresh);
+        if fresh {
+            Some(CynthiaCacheExtraction(
+                object.content,
+                object.timestamp.0,
+                object.content_type,
+            ))
         } else {
             trace!("Cache devaluate: {}", id);
             None
         }
     }
+    /// Returns a variant (like a compressed version) of a cached object, if one was stored.
+    pub(crate) fn get_cache_variant(&mut self, id: &str, variant: &str) -> Option<Vec<u8>> {
+        self.evaluate_cache();
+        self.cache
+            .iter()
+            .find(|x| x.id == id)?
+            .variants
+            .iter()
+            .find(|(name, _)| name == variant)
+            .map(|(_, contents)| contents.clone())
+    }
+    /// Stores a variant of a cached object. Does nothing if the object itself isn't cached (anymore).
+    pub(crate) fn store_cache_variant(&mut self, id: &str, variant: &str, contents: &[u8]) {
+        if let Some(object) = self.cache.iter_mut().find(|x| x.id == id) {
+            object.variants.retain(|(name, _)| name != variant);
+            object
+                .variants
+                .push((variant.to_string(), Vec::from(contents)));
+        }
+    }
     pub(crate) fn evaluate_cache(&mut self) {
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -97,7 +151,10 @@ impl ServerContext {
         self.cache.clear();
     }
     pub fn estimate_cache_size(&self) -> usize {
-        self.cache.iter().map(|x| x.content.len()).sum()
+        self.cache
+            .iter()
+            .map(|x| x.content.len() + x.variants.iter().map(|(_, v)| v.len()).sum::<usize>())
+            .sum()
     }
 }
 #[allow(dead_code)]
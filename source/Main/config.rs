@@ -9,6 +9,22 @@ pub(crate) struct CynthiaConf {
     #[serde(alias = "Port")]
     #[serde(default = "c_port")]
     pub(crate) port: u16,
+    /// Addresses to listen on. If empty, Cynthia listens on `localhost:{port}`.
+    #[serde(alias = "Listeners")]
+    #[serde(alias = "listen")]
+    #[serde(default = "c_listeners")]
+    pub(crate) listeners: Vec<Listener>,
+    /// When set, TCP listeners serve HTTPS using these certificates.
+    #[serde(alias = "TLS")]
+    #[serde(alias = "Tls")]
+    #[serde(alias = "https")]
+    #[serde(default)]
+    pub(crate) tls: Option<Tls>,
+    /// When set, Prometheus metrics are served on `/metrics` of a separate listener.
+    #[serde(alias = "Metrics")]
+    #[serde(alias = "prometheus")]
+    #[serde(default)]
+    pub(crate) metrics: Option<Metrics>,
     #[serde(alias = "Cache")]
     #[serde(default = "c_cache")]
     pub(crate) cache: Cache,
@@ -37,6 +53,9 @@ impl Default for CynthiaConf {
     fn default() -> Self {
         CynthiaConf {
             port: c_port(),
+            listeners: c_listeners(),
+            tls: None,
+            metrics: None,
             cache: Cache::default(),
             site: Site::default(),
             logs: c_logs(),
@@ -52,6 +71,7 @@ fn c_logs() -> Option<Logging> {
         file_loglevel: Some(3),
         term_loglevel: Some(2),
         logfile: Some(String::from("cynthia.log")),
+        access_log: None,
     })
 }
 
@@ -177,9 +197,118 @@ fn c_plugins() -> Vec<Plugin> {
     vec![]
 }
 
+#[derive(Debug, PartialEq, Serialize, Deserialize, StaticType, Clone)]
+pub(crate) struct Listener {
+    /// Either `host:port` (IPv6 hosts go between brackets, like `[::1]:3000`) or `unix:/path/to/socket`.
+    #[serde(alias = "addr")]
+    #[serde(alias = "bind")]
+    pub(crate) address: String,
+    /// Octal permissions (like `"660"`) to set on a unix domain socket after creating it.
+    #[serde(alias = "mode")]
+    #[serde(default)]
+    pub(crate) permissions: Option<String>,
+}
+
+pub(crate) enum ListenAddress {
+    Tcp(String),
+    Unix(std::path::PathBuf),
+}
+
+impl Listener {
+    pub(crate) fn get_address(&self) -> ListenAddress {
+        match self.address.strip_prefix("unix:") {
+            Some(path) => ListenAddress::Unix(std::path::PathBuf::from(path)),
+            None => ListenAddress::Tcp(self.address.clone()),
+        }
+    }
+    /// Parses the octal permissions string, if any.
+    pub(crate) fn get_permissions(&self) -> Result<Option<u32>, String> {
+        match &self.permissions {
+            None => Ok(None),
+            Some(p) => match u32::from_str_radix(p.trim_start_matches("0o"), 8) {
+                Ok(mode) if mode <= 0o777 => Ok(Some(mode)),
+                _ => Err(format!("`{}` is not a valid octal permission mode.", p)),
+            },
+        }
+    }
+    /// Returns how this listener should be presented in the startup output.
+    pub(crate) fn describe(&self, tls: bool) -> String {
+        match self.get_address() {
+            ListenAddress::Tcp(a) if tls => format!("https://{}", a),
+            ListenAddress::Tcp(a) => format!("http://{}", a),
+            ListenAddress::Unix(p) => format!("unix:{}", p.display()),
+        }
+    }
+}
+
+impl CynthiaConf {
+    /// Returns the configured listeners, or a listener on `localhost:{port}` when none are set.
+    pub(crate) fn get_listeners(&self) -> Vec<Listener> {
+        if self.listeners.is_empty() {
+            vec![Listener {
+                address: format!("localhost:{}", self.port),
+                permissions: None,
+            }]
+        } else {
+            self.listeners.clone()
+        }
+    }
+}
+
+fn c_listeners() -> Vec<Listener> {
+    vec![]
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, StaticType, Clone)]
+pub(crate) struct Tls {
+    /// Path to the PEM encoded certificate chain.
+    #[serde(alias = "certificate")]
+    #[serde(alias = "cert_file")]
+    pub(crate) cert: String,
+    /// Path to the PEM encoded private key.
+    #[serde(alias = "private_key")]
+    #[serde(alias = "key_file")]
+    pub(crate) key: String,
+    /// (Optional) A `host:port` to listen on with plain HTTP, redirecting everything to HTTPS.
+    #[serde(alias = "redirect_from")]
+    #[serde(alias = "http_redirect")]
+    #[serde(default)]
+    pub(crate) redirect: Option<String>,
+    /// How often (in seconds) the certificate files are checked for changes.
+    #[serde(alias = "reload-interval")]
+    #[serde(default = "c_tls_reload_interval")]
+    pub(crate) reload_interval: u64,
+}
+
+fn c_tls_reload_interval() -> u64 {
+    60
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, StaticType, Clone)]
+pub(crate) struct Metrics {
+    /// Either `host:port` or `unix:/path/to/socket`, like [`Listener::address`]. Keep this away
+    /// from the public internet, the metrics are not meant for visitors.
+    #[serde(alias = "addr")]
+    #[serde(alias = "bind")]
+    pub(crate) address: String,
+}
+
+impl Metrics {
+    /// The metrics listener, to bind and describe it like the others.
+    pub(crate) fn listener(&self) -> Listener {
+        Listener {
+            address: self.address.clone(),
+            permissions: None,
+        }
+    }
+}
+
 /// A clone of the CynthiaConf struct
 pub(crate) struct CynthiaConfClone {
     pub(crate) port: u16,
+    pub(crate) listeners: Vec<Listener>,
+    pub(crate) tls: Option<Tls>,
+    pub(crate) metrics: Option<Metrics>,
     pub(crate) cache: Cache,
     pub(crate) site: Site,
     pub(crate) logs: Option<Logging>,
@@ -192,6 +321,9 @@ impl CynthiaConfig for CynthiaConfClone {
     fn hard_clone(&self) -> CynthiaConf {
         CynthiaConf {
             port: self.port,
+            listeners: self.listeners.clone(),
+            tls: self.tls.clone(),
+            metrics: self.metrics.clone(),
             cache: self.cache.clone(),
             site: self.site.clone(),
             logs: self.logs.clone(),
@@ -203,6 +335,9 @@ impl CynthiaConfig for CynthiaConfClone {
     fn clone(&self) -> CynthiaConfClone {
         CynthiaConfClone {
             port: self.port,
+            listeners: self.listeners.clone(),
+            tls: self.tls.clone(),
+            metrics: self.metrics.clone(),
             cache: self.cache.clone(),
             site: self.site.clone(),
             logs: self.logs.clone(),
@@ -216,6 +351,9 @@ impl CynthiaConfig for CynthiaConf {
     fn hard_clone(&self) -> CynthiaConf {
         CynthiaConf {
             port: self.port,
+            listeners: self.listeners.clone(),
+            tls: self.tls.clone(),
+            metrics: self.metrics.clone(),
             cache: self.cache.clone(),
             site: self.site.clone(),
             logs: self.logs.clone(),
@@ -227,6 +365,9 @@ impl CynthiaConfig for CynthiaConf {
     fn clone(&self) -> CynthiaConfClone {
         CynthiaConfClone {
             port: self.port,
+            listeners: self.listeners.clone(),
+            tls: self.tls.clone(),
+            metrics: self.metrics.clone(),
             cache: self.cache.clone(),
             site: self.site.clone(),
             logs: self.logs.clone(),
@@ -245,6 +386,9 @@ impl CynthiaConf {
     pub(crate) fn clone(&self) -> CynthiaConfClone {
         CynthiaConfClone {
             port: self.port,
+            listeners: self.listeners.clone(),
+            tls: self.tls.clone(),
+            metrics: self.metrics.clone(),
             cache: self.cache.clone(),
             site: self.site.clone(),
             logs: self.logs.clone(),
@@ -255,7 +399,7 @@ impl CynthiaConf {
     }
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, StaticType)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, StaticType)]
 // #[serde(rename_all = "camelCase")]
 pub(crate) struct Cache {
     pub(crate) lifetimes: Lifetimes,
@@ -265,16 +409,39 @@ pub(crate) struct Cache {
     #[serde(alias = "max-cache-size")]
     #[serde(default = "c_max_cache_size")]
     pub(crate) max_cache_size: usize,
+
+    /// Assets larger than this (in bytes) are streamed from disk instead of being cached
+    /// Default: 8388608 (8MB)
+    #[serde(alias = "stream-threshold")]
+    #[serde(default = "c_stream_threshold")]
+    pub(crate) stream_threshold: u64,
+
+    /// Whether the query string is part of the cache key of rendered pages. Only needed when
+    /// templates change a page based on its query parameters
+    /// Default: false
+    #[serde(alias = "include-query")]
+    #[serde(default = "c_bool_false")]
+    pub(crate) include_query: bool,
+}
+impl Default for Cache {
+    fn default() -> Self {
+        c_cache()
+    }
 }
 fn c_cache() -> Cache {
     Cache {
         max_cache_size: c_max_cache_size(),
+        stream_threshold: c_stream_threshold(),
+        include_query: false,
         lifetimes: Lifetimes::default(),
     }
 }
 fn c_max_cache_size() -> usize {
     536870912
 }
+fn c_stream_threshold() -> u64 {
+    8388608
+}
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, StaticType)]
 // #[serde(rename_all = "camelCase")]
@@ -321,6 +488,35 @@ pub(crate) struct Site {
     pub(crate) og_sitename: String,
 
     pub(crate) meta: Meta,
+
+    /// What to do with a trailing slash on a publication URL.
+    #[serde(alias = "trailing-slash")]
+    #[serde(alias = "trailingSlash")]
+    #[serde(default)]
+    pub(crate) trailing_slash: TrailingSlash,
+
+    /// Whether publication URLs match regardless of case, redirecting to the URL with the case
+    /// used in the publication id.
+    #[serde(alias = "case-insensitive")]
+    #[serde(alias = "caseInsensitive")]
+    #[serde(default = "c_bool_true")]
+    pub(crate) case_insensitive: bool,
+
+    /// How many posts a postlist shows per page, unless the postlist sets its own `per_page`.
+    /// Tag and category lists always use this. `0` shows all posts on one page.
+    #[serde(alias = "per-page")]
+    #[serde(alias = "perPage")]
+    #[serde(default)]
+    pub(crate) per_page: usize,
+
+    /// The timezone dates are shown and grouped in: `utc`, `local` for the timezone of the server,
+    /// or an offset like `+02:00`.
+    #[serde(default = "c_timezone")]
+    pub(crate) timezone: String,
+
+    /// What `/robots.txt` and the robots directives of publications say.
+    #[serde(default)]
+    pub(crate) robots: Robots,
 }
 
 impl Default for Site {
@@ -329,17 +525,138 @@ impl Default for Site {
             notfound_page: String::from("404"),
             site_baseurl: String::new(),
             og_sitename: String::new(),
-            meta: Meta { enable_tags: false },
+            meta: Meta {
+                enable_tags: false,
+                enable_rss: true,
+                enable_atom: true,
+                enable_json_feed: true,
+                feed_content: false,
+                enable_sitemap: true,
+                enable_api: false,
+            },
+            trailing_slash: TrailingSlash::default(),
+            case_insensitive: true,
+            per_page: 0,
+            timezone: c_timezone(),
+            robots: Robots::default(),
+        }
+    }
+}
+
+impl Site {
+    /// The offset from UTC of `timezone` at `unix` time, or `None` if `timezone` isn't understood.
+    pub(crate) fn utc_offset(&self, unix: i64) -> Option<chrono::FixedOffset> {
+        let timezone = self.timezone.trim();
+        match timezone.to_lowercase().as_str() {
+            "" | "utc" | "gmt" | "z" => chrono::FixedOffset::east_opt(0),
+            "local" => {
+                use chrono::{Offset, TimeZone};
+                let utc = chrono::DateTime::from_timestamp(unix, 0)?.naive_utc();
+                Some(chrono::Local.offset_from_utc_datetime(&utc).fix())
+            }
+            _ => timezone
+                .trim_start_matches("UTC")
+                .trim_start_matches("utc")
+                .parse()
+                .ok(),
+        }
+    }
+
+    /// The calendar date of `unix` time in the configured timezone.
+    pub(crate) fn local_date(&self, unix: u64) -> chrono::NaiveDate {
+        let unix = unix as i64;
+        let offset = self
+            .utc_offset(unix)
+            .unwrap_or(chrono::FixedOffset::east_opt(0).unwrap());
+        chrono::DateTime::from_timestamp(unix, 0)
+            .unwrap_or_default()
+            .with_timezone(&offset)
+            .date_naive()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, StaticType)]
+pub(crate) struct Robots {
+    /// Serve `/robots.txt`.
+    #[serde(alias = "robots-txt")]
+    #[serde(alias = "robotsTxt")]
+    #[serde(default = "c_bool_true")]
+    pub(crate) robots_txt: bool,
+
+    /// Paths no crawler should visit, listed in `/robots.txt`.
+    #[serde(default)]
+    pub(crate) disallow: Vec<String>,
+
+    /// The robots directives of publications that don't set their own, sent as both a
+    /// `<meta name="robots">` tag and an `X-Robots-Tag` header.
+    #[serde(default = "c_robots_directives")]
+    pub(crate) directives: String,
+}
+
+impl Default for Robots {
+    fn default() -> Self {
+        Robots {
+            robots_txt: true,
+            disallow: vec![],
+            directives: c_robots_directives(),
         }
     }
 }
 
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize, StaticType)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum TrailingSlash {
+    /// `/hello-world/` redirects to `/hello-world`.
+    #[default]
+    Strip,
+    /// `/hello-world` redirects to `/hello-world/`.
+    Add,
+    /// Both are served, without redirecting.
+    Ignore,
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, StaticType)]
 pub(crate) struct Meta {
     #[serde(alias = "enable-tags")]
     #[serde(alias = "enableTags")]
     #[serde(default = "c_bool_false")]
     pub(crate) enable_tags: bool,
+
+    /// Serve an RSS 2.0 feed for every postlist, on `/{postlist}/feed.xml`.
+    #[serde(alias = "enable-rss")]
+    #[serde(alias = "enableRss")]
+    #[serde(default = "c_bool_true")]
+    pub(crate) enable_rss: bool,
+
+    /// Serve an Atom feed for every postlist, on `/{postlist}/atom.xml`.
+    #[serde(alias = "enable-atom")]
+    #[serde(alias = "enableAtom")]
+    #[serde(default = "c_bool_true")]
+    pub(crate) enable_atom: bool,
+
+    /// Serve a JSON Feed for every postlist, on `/{postlist}/feed.json`.
+    #[serde(alias = "enable-json-feed")]
+    #[serde(alias = "enableJsonFeed")]
+    #[serde(default = "c_bool_true")]
+    pub(crate) enable_json_feed: bool,
+
+    /// Put the full content of posts in feeds, rather than only their description.
+    #[serde(alias = "feed-content")]
+    #[serde(alias = "feedContent")]
+    #[serde(default = "c_bool_false")]
+    pub(crate) feed_content: bool,
+
+    /// Serve `/sitemap.xml`, listing the publications for search engines.
+    #[serde(alias = "enable-sitemap")]
+    #[serde(alias = "enableSitemap")]
+    #[serde(default = "c_bool_true")]
+    pub(crate) enable_sitemap: bool,
+
+    /// Serve the read-only JSON API on `/api/`.
+    #[serde(alias = "enable-api")]
+    #[serde(alias = "enableApi")]
+    #[serde(default = "c_bool_false")]
+    pub(crate) enable_api: bool,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, StaticType)]
@@ -357,6 +674,35 @@ pub(crate) struct Logging {
     #[serde(alias = "file")]
     #[serde(alias = "filename")]
     pub(crate) logfile: Option<String>,
+    /// A log of every request, for log analysers. Not written when unset.
+    #[serde(alias = "access-log")]
+    #[serde(alias = "accesslog")]
+    #[serde(default)]
+    pub(crate) access_log: Option<AccessLog>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, StaticType)]
+pub(crate) struct AccessLog {
+    /// The file to append the access log to.
+    #[serde(alias = "file")]
+    #[serde(alias = "filename")]
+    pub(crate) path: String,
+    #[serde(default)]
+    pub(crate) format: AccessLogFormat,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize, StaticType)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum AccessLogFormat {
+    /// The Combined Log Format, followed by the duration in microseconds and where the response
+    /// came from.
+    #[default]
+    #[serde(alias = "clf")]
+    Combined,
+    /// One JSON object per line.
+    #[serde(alias = "jsonl")]
+    #[serde(alias = "ndjson")]
+    Json,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, StaticType)]
@@ -398,9 +744,15 @@ fn c_port() -> u16 {
 fn c_bool_false() -> bool {
     false
 }
+fn c_bool_true() -> bool {
+    true
+}
 fn c_emptystring() -> String {
     String::from("")
 }
+fn c_robots_directives() -> String {
+    String::from("index, follow")
+}
 fn c_cache_lifetime_stylesheets() -> u64 {
     72000
 }
@@ -414,6 +766,9 @@ fn c_cache_lifetime_served() -> u64 {
     50
 }
 
+fn c_timezone() -> String {
+    String::from("utc")
+}
 fn c_404() -> String {
     String::from("404")
 }
@@ -83,7 +83,7 @@ fn choose_config_location() -> ConfigLocations {
 pub(crate) fn load_config() -> CynthiaConf {
     use jsonc_parser::parse_to_serde_value as preparse_jsonc;
     let chosen_config_location = choose_config_location();
-    return match chosen_config_location {
+    match chosen_config_location {
         ConfigLocations::JsonC(cynthiaconfpath) => {
             println!(
                 "{} Loading: {}",
@@ -322,7 +322,7 @@ pub(crate) fn load_config() -> CynthiaConf {
                 }
             }
         }
-    };
+    }
 }
 
 pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
@@ -336,51 +336,53 @@ pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
     {
         let chosen_config_location = choose_config_location_option();
         match chosen_config_location {
-            Some(ConfigLocations::Js(_)) => {
-                if to == "js" {
-                    eprintln!(
-                        "{} You are trying to convert a JavaScript configuration to JavaScript. This is not possible.",
-                        "error:".color_red()
-                    );
-                    process::exit(1);
-                }
+            Some(ConfigLocations::Js(_)) if to == "js" => {
+                eprintln!(
+                    "{} You are trying to convert a JavaScript configuration to JavaScript. This is not possible.",
+                    "error:".color_red()
+                );
+                process::exit(1);
             }
-            Some(ConfigLocations::Dhall(_)) => {
-                if to == "dhall" {
-                    eprintln!(
-                        "{} You are trying to convert a Dhall configuration to Dhall. This is not possible.",
-                        "error:".color_red()
-                    );
-                    process::exit(1);
-                }
+            Some(ConfigLocations::Dhall(_)) if to == "dhall" => {
+                eprintln!(
+                    "{} You are trying to convert a Dhall configuration to Dhall. This is not possible.",
+                    "error:".color_red()
+                );
+                process::exit(1);
             }
-            Some(ConfigLocations::Toml(_)) => {
-                if to == "toml" {
-                    eprintln!(
-                        "{} You are trying to convert a TOML configuration to TOML. This is not possible.",
-                        "error:".color_red()
-                    );
-                    process::exit(1);
-                }
+            Some(ConfigLocations::Toml(_)) if to == "toml" => {
+                eprintln!(
+                    "{} You are trying to convert a TOML configuration to TOML. This is not possible.",
+                    "error:".color_red()
+                );
+                process::exit(1);
             }
-            Some(ConfigLocations::JsonC(_)) => {
-                if to == "jsonc" {
-                    eprintln!(
-                        "{} You are trying to convert a JSONC configuration to JSONC. This is not possible.",
-                        "error:".color_red()
-                    );
-                    process::exit(1);
-                }
+            Some(ConfigLocations::JsonC(_)) if to == "jsonc" => {
+                eprintln!(
+                    "{} You are trying to convert a JSONC configuration to JSONC. This is not possible.",
+                    "error:".color_red()
+                );
+                process::exit(1);
             }
-            None => {}
+            _ => {}
         }
     }
     let cynthiaconfdoclink = r#"https://strawmelonjuice.github.io/CynthiaWebsiteEngine/Admins/configuration/CynthiaConf.html"#;
     let args: Vec<String> = std::env::args().collect();
     let cd = std::env::current_dir().unwrap();
     // as a tuple, the first element is the key, the second is the comment, the third is the key in the config.
-    let comments: [(&str, &str, &str); 32] = [
+    let comments: [(&str, &str, &str); 56] = [
         ("port", "The port on which Cynthia hosts, since Cynthia was designed to be reverse-proxied, this port is usually higher than 1000.", "port"),
+        ("listeners", "(Optional) The addresses Cynthia listens on. If none are set, Cynthia listens on `localhost` on the port above.", "listeners"),
+            ("address", "Either a `host:port` pair (use `[::]:3000` for IPv6), or `unix:/path/to/socket` for a unix domain socket.", "listeners.address"),
+            ("permissions", "(Optional) Octal permissions to set on a unix domain socket, like `660`.", "listeners.permissions"),
+        ("tls", "(Optional) Serve HTTPS on all TCP listeners, using the certificate set here.", "tls"),
+            ("cert", "Path to the PEM encoded certificate chain.", "tls.cert"),
+            ("key", "Path to the PEM encoded private key.", "tls.key"),
+            ("redirect", "(Optional) A `host:port` to listen on with plain HTTP, which redirects all requests to HTTPS.", "tls.redirect"),
+            ("reload_interval", "How often (in seconds) to check the certificate files for changes. Changed certificates are loaded without restarting.", "tls.reload_interval"),
+        ("metrics", "(Optional) Serve Prometheus metrics on `/metrics`, on a separate listener at `address`. Keep it away from the public internet.", "metrics"),
+            ("address", "Either a `host:port` pair or `unix:/path/to/socket`, like the listeners above.", "metrics.address"),
         ("cache", "The cache configuration for Cynthia.", "cache"),
             ("lifetimes", "These rules are set for a reason: The higher they are set, the less requests we have to do to Node, external servers, etc.\nHigher caching might consume a lot of memory or storage and crash the system.\nCaching can speed up Cynthia a whole lot, so think wisely before you change any of these numbers!", "cache.lifetimes"),
                 ("stylesheets", "How long (in seconds) to cache a CSS file after having minified and served it.", "cache.lifetimes.stylesheets"),
@@ -394,15 +396,29 @@ pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
             ("meta", "Meta settings for generation, not setting 'how', but 'what' to generate.", "site.meta"),
                 ("enable_tags", "Enables or disables pagetags in HTML metatags,\nthese are officially supposed to be good for\nfinding a website, but have been known to\nget nerfed by Google, considering them spam.", "site.meta.enable_tags"),
                 ("enable_search", "Whether to enable search or not. If enabled, search will be used to generate pages.", "site.meta.enable_search"),
-                ("enable_sitemap", "Whether to enable sitemap or not. If enabled, sitemap will be used to generate pages.", "site.meta.enable_sitemap"),
-                ("enable_rss", "Whether to enable RSS or not. If enabled, RSS will be used to generate pages.", "site.meta.enable_rss"),
-                ("enable_atom", "Whether to enable Atom or not. If enabled, Atom will be used to generate pages.", "site.meta.enable_atom"),
+                ("enable_sitemap", "Whether to serve `/sitemap.xml`, listing the publications for search engines. Uses `site_baseurl` for its links.", "site.meta.enable_sitemap"),
+                ("enable_rss", "Whether to serve an RSS feed for every postlist, on `/{postlist}/feed.xml`.", "site.meta.enable_rss"),
+                ("enable_atom", "Whether to serve an Atom feed for every postlist, on `/{postlist}/atom.xml`.", "site.meta.enable_atom"),
+                ("enable_json_feed", "Whether to serve a JSON Feed for every postlist, on `/{postlist}/feed.json`.", "site.meta.enable_json_feed"),
+                ("feed_content", "Whether feeds include the full content of posts, instead of only their description.", "site.meta.feed_content"),
+                ("enable_api", "Whether to serve the read-only JSON API on `/api/`, for using Cynthia as a headless CMS. Off by default.", "site.meta.enable_api"),
             ("site_baseurl", "The base URL of the site, used for generating links.", "site.site_baseurl"),
             ("og_sitename", "Site name for the site, this is different than the site name set in scenes, as it is mostly used for embeds, and so get's cached on url.", "site.og_sitename"),
+            ("trailing_slash", "What to do with a trailing slash on publication URLs: `strip` redirects `/id/` to `/id`, `add` redirects `/id` to `/id/` and `ignore` serves both.", "site.trailing_slash"),
+            ("case_insensitive", "Whether publication URLs match regardless of case. Differently cased URLs are redirected to the one used in the publication id.", "site.case_insensitive"),
+            ("per_page", "How many posts a postlist shows per page, unless it sets its own `per_page`. Tag and category lists always use this. Set to 0 to show all posts on one page.", "site.per_page"),
+            ("timezone", "The timezone dates are shown and grouped in, like in the archive: `utc`, `local` for the timezone of the server, or an offset like `+02:00`.", "site.timezone"),
+            ("robots", "What crawlers are told, in `/robots.txt` and on every publication.", "site.robots"),
+                ("robots_txt", "Whether to serve `/robots.txt`. It links to the sitemap if that is enabled.", "site.robots.robots_txt"),
+                ("disallow", "Paths no crawler should visit, like `/private`.", "site.robots.disallow"),
+                ("directives", "The robots directives of publications that don't set their own, sent as both a meta tag and an `X-Robots-Tag` header. The 404 page always gets `noindex`.", "site.robots.directives"),
         ("logs", "The log configuration for Cynthia.", "logs"),
             ("term_loglevel", "The minimum level of importance (1-5) before Cynthia logs to the terminal.", "logs.term_loglevel"),
             ("file_loglevel", "The minimum level of importance (1-5) before Cynthia logs to a file.", "logs.file_loglevel"),
             ("log_file", "The file Cynthia logs to.", "logs.log_file"),
+            ("access_log", "(Optional) Write a line for every request to an access log, for log analysers like GoAccess.", "logs.access_log"),
+                ("path", "The file to append the access log to.", "logs.access_log.path"),
+                ("format", "Either `combined` (the Combined Log Format, followed by the duration in microseconds and whether the response came from the cache, was generated, or came from a plugin) or `json` (one JSON object per line).", "logs.access_log.format"),
         ("scenes", "Scenes allow Cynthia to switch it's behaviour and themes completely for certain pages.", "scenes"),
                 ("name", "The id of the scene, used for linking. Set to `default` for the default scene.", "scenes.name"),
                 ("sitename", "The name Cynthia uses for presenting the site when using this scene.", "scenes.sitename"),
@@ -434,53 +450,89 @@ pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
             }
             o.clone()
         };
-        serde_json::to_string_pretty(&config)
-            .unwrap()
-            .replace("\"port\":", &comment_this("port"))
-            .replace("\"cache\":", &comment_this("cache"))
-            .replace("\"lifetimes\":", &comment_this("cache.lifetimes"))
-            .replace("\"forwarded\":", &comment_this("cache.lifetimes.forwarded"))
-            .replace(
-                "\"javascript\":",
-                &comment_this("cache.lifetimes.javascript"),
-            )
-            .replace("\"served\":", &comment_this("cache.lifetimes.served"))
-            .replace(
-                "\"stylesheets\":",
-                &comment_this("cache.lifetimes.stylesheets"),
-            )
-            .replace("\"runtimes\":", &comment_this("runtimes"))
-            .replace("\"ext_js_rt\":", &comment_this("runtimes.ext_js_rt"))
-            .replace("\"pages\":", &comment_this("pages"))
-            .replace("\"notfound_page\":", &comment_this("site.notfound_page"))
-            .replace("\"site\":", &comment_this("site"))
-            .replace("\"meta\":", &comment_this("site.meta"))
-            .replace("\"enable_tags\":", &comment_this("site.meta.enable_tags"))
-            .replace(
-                "\"enable_search\":",
-                &comment_this("site.meta.enable_search"),
-            )
-            .replace(
-                "\"enable_sitemap\":",
-                &comment_this("site.meta.enable_sitemap"),
-            )
-            .replace("\"enable_rss\":", &comment_this("site.meta.enable_rss"))
-            .replace("\"enable_atom\":", &comment_this("site.meta.enable_atom"))
-            .replace("\"site_baseurl\":", &comment_this("site.site_baseurl"))
-            .replace("\"og_sitename\":", &comment_this("site.og_sitename"))
-            .replace("\"logs\":", &comment_this("logs"))
-            .replace("\"term_loglevel\":", &comment_this("logs.term_loglevel"))
-            .replace("\"file_loglevel\":", &comment_this("logs.file_loglevel"))
-            .replace("\"log_file\":", &comment_this("logs.log_file"))
-            .replace("\"scenes\":", &comment_this("scenes"))
-            .replace("\"name\":", &comment_this("scenes.name"))
-            .replace("\"sitename\":", &comment_this("scenes.sitename"))
-            .replace("\"script\":", &comment_this("scenes.script"))
-            .replace("\"stylefile\":", &comment_this("scenes.stylefile"))
-            .replace("\"templates\":", &comment_this("scenes.templates"))
-            .replace("\"page\":", &comment_this("scenes.templates.page"))
-            .replace("\"post\":", &comment_this("scenes.templates.post"))
-            .replace("\"postlist\":", &comment_this("scenes.templates.postlist"))
+        // Keys that are generic enough to show up in other sections are only replaced within their own.
+        [
+            ("\"listeners\":", "\"address\":", "listeners.address"),
+            ("\"metrics\":", "\"address\":", "metrics.address"),
+            ("\"access_log\":", "\"path\":", "logs.access_log.path"),
+            ("\"access_log\":", "\"format\":", "logs.access_log.format"),
+        ]
+        .iter()
+        .fold(
+            serde_json::to_string_pretty(&config).unwrap(),
+            |json, (parent, key, item)| replace_in_section(&json, parent, key, &comment_this(item)),
+        )
+        .replace("\"port\":", &comment_this("port"))
+        .replace("\"listeners\":", &comment_this("listeners"))
+        .replace("\"permissions\":", &comment_this("listeners.permissions"))
+        .replace("\"tls\":", &comment_this("tls"))
+        .replace("\"cert\":", &comment_this("tls.cert"))
+        .replace("\"key\":", &comment_this("tls.key"))
+        .replace("\"redirect\":", &comment_this("tls.redirect"))
+        .replace("\"reload_interval\":", &comment_this("tls.reload_interval"))
+        .replace("\"metrics\":", &comment_this("metrics"))
+        .replace("\"cache\":", &comment_this("cache"))
+        .replace("\"lifetimes\":", &comment_this("cache.lifetimes"))
+        .replace("\"forwarded\":", &comment_this("cache.lifetimes.forwarded"))
+        .replace(
+            "\"javascript\":",
+            &comment_this("cache.lifetimes.javascript"),
+        )
+        .replace("\"served\":", &comment_this("cache.lifetimes.served"))
+        .replace(
+            "\"stylesheets\":",
+            &comment_this("cache.lifetimes.stylesheets"),
+        )
+        .replace("\"runtimes\":", &comment_this("runtimes"))
+        .replace("\"ext_js_rt\":", &comment_this("runtimes.ext_js_rt"))
+        .replace("\"pages\":", &comment_this("pages"))
+        .replace("\"notfound_page\":", &comment_this("site.notfound_page"))
+        .replace("\"site\":", &comment_this("site"))
+        .replace("\"meta\":", &comment_this("site.meta"))
+        .replace("\"enable_tags\":", &comment_this("site.meta.enable_tags"))
+        .replace(
+            "\"enable_search\":",
+            &comment_this("site.meta.enable_search"),
+        )
+        .replace(
+            "\"enable_sitemap\":",
+            &comment_this("site.meta.enable_sitemap"),
+        )
+        .replace("\"enable_rss\":", &comment_this("site.meta.enable_rss"))
+        .replace("\"enable_atom\":", &comment_this("site.meta.enable_atom"))
+        .replace(
+            "\"enable_json_feed\":",
+            &comment_this("site.meta.enable_json_feed"),
+        )
+        .replace("\"feed_content\":", &comment_this("site.meta.feed_content"))
+        .replace("\"enable_api\":", &comment_this("site.meta.enable_api"))
+        .replace("\"site_baseurl\":", &comment_this("site.site_baseurl"))
+        .replace("\"og_sitename\":", &comment_this("site.og_sitename"))
+        .replace("\"trailing_slash\":", &comment_this("site.trailing_slash"))
+        .replace(
+            "\"case_insensitive\":",
+            &comment_this("site.case_insensitive"),
+        )
+        .replace("\"per_page\":", &comment_this("site.per_page"))
+        .replace("\"timezone\":", &comment_this("site.timezone"))
+        .replace("\"robots\":", &comment_this("site.robots"))
+        .replace("\"robots_txt\":", &comment_this("site.robots.robots_txt"))
+        .replace("\"disallow\":", &comment_this("site.robots.disallow"))
+        .replace("\"directives\":", &comment_this("site.robots.directives"))
+        .replace("\"logs\":", &comment_this("logs"))
+        .replace("\"term_loglevel\":", &comment_this("logs.term_loglevel"))
+        .replace("\"file_loglevel\":", &comment_this("logs.file_loglevel"))
+        .replace("\"log_file\":", &comment_this("logs.log_file"))
+        .replace("\"access_log\":", &comment_this("logs.access_log"))
+        .replace("\"scenes\":", &comment_this("scenes"))
+        .replace("\"name\":", &comment_this("scenes.name"))
+        .replace("\"sitename\":", &comment_this("scenes.sitename"))
+        .replace("\"script\":", &comment_this("scenes.script"))
+        .replace("\"stylefile\":", &comment_this("scenes.stylefile"))
+        .replace("\"templates\":", &comment_this("scenes.templates"))
+        .replace("\"page\":", &comment_this("scenes.templates.page"))
+        .replace("\"post\":", &comment_this("scenes.templates.post"))
+        .replace("\"postlist\":", &comment_this("scenes.templates.postlist"))
     };
 
     let config_serialised: String = match to {
@@ -511,20 +563,39 @@ pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
                 }
                 o.clone()
             };
+            let dhall = serde_dhall::serialize(&config)
+                .static_type_annotation()
+                .to_string()
+                .unwrap()
+                .chars()
+                .skip(1)
+                .collect::<String>()
+                // .replace(",", ",\n")
+                .replace(",", "\n,")
+                .replace("{", "{\n")
+                .replace("}", "\n}\n")
+                .replace("\n", "\n ");
+            let dhall = [
+                (" listeners =", " address =", "listeners.address"),
+                (" metrics =", " address =", "metrics.address"),
+                (" access_log =", " path =", "logs.access_log.path"),
+                (" access_log =", " format =", "logs.access_log.format"),
+            ]
+            .iter()
+            .fold(dhall, |dhall, (parent, key, item)| {
+                replace_in_section(&dhall, parent, key, &comment_this(item))
+            });
             format!("{{\n{{-\n\tThis is the configuration file for Cynthia. It is written in Dhall, a Haskell-like language that is able to contain functions and types.\n\tMore info about this config can be found on <{cynthiaconfdoclink}>\n\n\tTo convert it to another config language, use the `cynthiaweb convert` command.\n-}}\n{}",
-                serde_dhall::serialize(&config)
-                    .static_type_annotation()
-                    .to_string()
-                    .unwrap()
-                    .chars()
-                    .skip(1)
-                    .collect::<String>()
-                    // .replace(",", ",\n")
-                    .replace(",", "\n,")
-                    .replace("{", "{\n")
-                    .replace("}", "\n}\n")
-                    .replace("\n", "\n ")
+                dhall
                     .replace(" port =", &comment_this("port"))
+                    .replace(" listeners =", &comment_this("listeners"))
+                        .replace(" permissions =", &comment_this("listeners.permissions"))
+                    .replace(" tls =", &comment_this("tls"))
+                        .replace(" cert =", &comment_this("tls.cert"))
+                        .replace(" key =", &comment_this("tls.key"))
+                        .replace(" redirect =", &comment_this("tls.redirect"))
+                        .replace(" reload_interval =", &comment_this("tls.reload_interval"))
+                    .replace(" metrics =", &comment_this("metrics"))
                     .replace(" cache =", &comment_this("cache"))
                     .replace(
                         " lifetimes =",
@@ -545,12 +616,24 @@ pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
                             .replace(" enable_sitemap =", &comment_this("site.meta.enable_sitemap"))
                             .replace(" enable_rss =", &comment_this("site.meta.enable_rss"))
                             .replace(" enable_atom =", &comment_this("site.meta.enable_atom"))
+                            .replace(" enable_json_feed =", &comment_this("site.meta.enable_json_feed"))
+                            .replace(" feed_content =", &comment_this("site.meta.feed_content"))
+                            .replace(" enable_api =", &comment_this("site.meta.enable_api"))
                         .replace(" site_baseurl =", &comment_this("site.site_baseurl"))
                         .replace(" og_sitename =", &comment_this("site.og_sitename"))
+                        .replace(" trailing_slash =", &comment_this("site.trailing_slash"))
+                        .replace(" case_insensitive =", &comment_this("site.case_insensitive"))
+                        .replace(" per_page =", &comment_this("site.per_page"))
+                        .replace(" timezone =", &comment_this("site.timezone"))
+                        .replace(" robots =", &comment_this("site.robots"))
+                            .replace(" robots_txt =", &comment_this("site.robots.robots_txt"))
+                            .replace(" disallow =", &comment_this("site.robots.disallow"))
+                            .replace(" directives =", &comment_this("site.robots.directives"))
                     .replace(" logs =", &comment_this("logs"))
                         .replace(" term_loglevel =", &comment_this("logs.term_loglevel"))
                         .replace(" file_loglevel =", &comment_this("logs.file_loglevel"))
                         .replace(" log_file =", &comment_this("logs.log_file"))
+                        .replace(" access_log =", &comment_this("logs.access_log"))
                     .replace(" scenes =", &comment_this("scenes"))
                         .replace(" name =", &comment_this("scenes.name"))
                         .replace(" sitename =", &comment_this("scenes.sitename"))
@@ -580,10 +663,29 @@ pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
                 }
                 o.clone()
             };
-            format!("# Cynthia.toml\n# \n# This is the configuration file for Cynthia. It is written in TOML, a YAML-like language that is focused on user readability.\n# More info about this config can be found on <{cynthiaconfdoclink}>\n# \n# \n# To convert it to another config language, use the `cynthiaweb convert` command.\n\n\n{}", toml::to_string_pretty(&config)
+            let toml = toml::to_string_pretty(&config)
                 .unwrap()
-                .replace("\n","\n ")
+                .replace("\n", "\n ");
+            let toml = [
+                (" [[listeners]]", " address = ", "listeners.address"),
+                (" [metrics]", " address = ", "metrics.address"),
+                (" [logs.access_log]", " path = ", "logs.access_log.path"),
+                (" [logs.access_log]", " format = ", "logs.access_log.format"),
+            ]
+            .iter()
+            .fold(toml, |toml, (parent, key, item)| {
+                replace_in_section(&toml, parent, key, &comment_this(item))
+            });
+            format!("# Cynthia.toml\n# \n# This is the configuration file for Cynthia. It is written in TOML, a YAML-like language that is focused on user readability.\n# More info about this config can be found on <{cynthiaconfdoclink}>\n# \n# \n# To convert it to another config language, use the `cynthiaweb convert` command.\n\n\n{}", toml
                 .replace(" port = ", &comment_this("port"))
+                .replace(" [[listeners]]", comment_this("listeners").replace("listeners = ", "[[listeners]]").as_str())
+                    .replace(" permissions = ", &comment_this("listeners.permissions"))
+                .replace(" [tls]", comment_this("tls").replace("tls = ", "[tls]").as_str())
+                    .replace(" cert = ", &comment_this("tls.cert"))
+                    .replace(" key = ", &comment_this("tls.key"))
+                    .replace(" redirect = ", &comment_this("tls.redirect"))
+                    .replace(" reload_interval = ", &comment_this("tls.reload_interval"))
+                .replace(" [metrics]", comment_this("metrics").replace("metrics = ", "[metrics]").as_str())
                 .replace(
                     " [cache.lifetimes]",
                     comment_this("cache.lifetimes")
@@ -625,8 +727,24 @@ pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
                 .replace(" enable_sitemap = ", &comment_this("site.meta.enable_sitemap"))
                 .replace(" enable_rss = ", &comment_this("site.meta.enable_rss"))
                 .replace(" enable_atom = ", &comment_this("site.meta.enable_atom"))
+                .replace(" enable_json_feed = ", &comment_this("site.meta.enable_json_feed"))
+                .replace(" feed_content = ", &comment_this("site.meta.feed_content"))
+                .replace(" enable_api = ", &comment_this("site.meta.enable_api"))
                 .replace(" site_baseurl = ", &comment_this("site.site_baseurl"))
                 .replace(" og_sitename = ", &comment_this("site.og_sitename"))
+                .replace(" trailing_slash = ", &comment_this("site.trailing_slash"))
+                .replace(" case_insensitive = ", &comment_this("site.case_insensitive"))
+                .replace(" per_page = ", &comment_this("site.per_page"))
+                .replace(" timezone = ", &comment_this("site.timezone"))
+                .replace(
+                    " [site.robots]",
+                    comment_this("site.robots")
+                        .replace("robots = ", "[site.robots]")
+                        .as_str(),
+                )
+                .replace(" robots_txt = ", &comment_this("site.robots.robots_txt"))
+                .replace(" disallow = ", &comment_this("site.robots.disallow"))
+                .replace(" directives = ", &comment_this("site.robots.directives"))
                 .replace(
                     " [logs]",
                     comment_this("logs")
@@ -636,6 +754,12 @@ pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
                     .replace(" term_loglevel = ", &comment_this("logs.term_loglevel"))
                     .replace(" file_loglevel = ", &comment_this("logs.file_loglevel"))
                     .replace(" log_file = ", &comment_this("logs.log_file"))
+                .replace(
+                    " [logs.access_log]",
+                    comment_this("logs.access_log")
+                        .replace("access_log = ", "[logs.access_log]")
+                        .as_str(),
+                )
                 .replace(" [[scenes]]", comment_this("scenes").replace("scenes = ", "[[scenes]]").as_str())
                     .replace(" name = ", &comment_this("scenes.name"))
                     .replace(" sitename = ", &comment_this("scenes.sitename"))
@@ -729,6 +853,62 @@ pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
     process::exit(0);
 }
 
+/// Replaces `key` with `with`, but only within the sections that start with `parent`, for keys
+/// that more than one section has. A section ends where the bracket its value opens with closes,
+/// or, for TOML tables, at the next table header.
+fn replace_in_section(text: &str, parent: &str, key: &str, with: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(found) = rest.find(parent) {
+        let (before, after) = rest.split_at(found + parent.len());
+        out.push_str(before);
+        let value = after.trim_start();
+        let value = value.strip_prefix("Some").unwrap_or(value).trim_start();
+        let end = if value.starts_with(['{', '[']) {
+            after.len() - value.len() + section_length(value)
+        } else if let Some(table) = after.strip_prefix('\n') {
+            table.find("\n [").map_or(after.len(), |i| i + 1)
+        } else {
+            // Nothing in here, like `null` or `None`.
+            0
+        };
+        out.push_str(&after[..end].replace(key, with));
+        rest = &after[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The length of the bracketed value `text` starts with, up to and including its closing bracket.
+fn section_length(text: &str) -> usize {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' | '[' => depth += 1,
+            '}' | ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    text.len()
+}
+
 pub(crate) fn choose_config_location_option() -> Option<ConfigLocations> {
     let cd = std::env::current_dir().unwrap();
     // In order of preference for Cynthia. I personally prefer TOML, but Cynthia would prefer Dhall. Besides, Dhall is far more powerful.
@@ -751,3 +931,42 @@ pub(crate) fn choose_config_location_option() -> Option<ConfigLocations> {
     }
     a.map(|p| config_locations[p].clone())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sections() {
+        let json = "{\n  \"listeners\": [\n    {\n      \"address\": \"[::]:3000\"\n    }\n  ],\n  \"metrics\": {\n    \"address\": \"localhost:9000\"\n  }\n}";
+        let json = replace_in_section(
+            json,
+            "\"metrics\":",
+            "\"address\":",
+            "// metrics\n\"address\":",
+        );
+        assert_eq!(json.matches("// metrics").count(), 1);
+        assert!(json.contains("// metrics\n\"address\": \"localhost:9000\""));
+
+        let toml = "\n [[listeners]]\n address = \"[::]:3000\"\n \n [[listeners]]\n address = \"unix:/run/c.sock\"\n \n [metrics]\n address = \"localhost:9000\"\n";
+        let toml = replace_in_section(
+            toml,
+            " [[listeners]]",
+            " address = ",
+            " # listener\n address = ",
+        );
+        assert_eq!(toml.matches("# listener").count(), 2);
+        assert!(toml.ends_with("[metrics]\n address = \"localhost:9000\"\n"));
+
+        let dhall = " listeners = None (List { address : Text })\n, metrics = Some {\n address = \"}\"\n}\n, port = 3000";
+        let dhall = replace_in_section(dhall, " listeners =", " address =", "X");
+        let dhall = replace_in_section(
+            &dhall,
+            " metrics =",
+            " address =",
+            " -- metrics\n address =",
+        );
+        assert!(dhall.contains(" -- metrics\n address = \"}\""));
+        assert!(!dhall.contains('X'));
+    }
+}
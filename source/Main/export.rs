@@ -0,0 +1,280 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+
+//! Static site export (`cynthiaweb build`).
+//!
+//! Walks the publication list and writes every publication, as rendered by the same renderer the
+//! server uses, to an output folder. Together with a copy of `cynthiaFiles/assets` that folder can
+//! be hosted on any plain static file host.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use actix_web::web::Data;
+use log::error;
+use tokio::sync::Mutex;
+
+use crate::config::{CynthiaConfClone, CynthiaConfig};
+use crate::feeds::{self, FeedKind};
+use crate::publications::{
+    self, url_path, CynthiaPublication, CynthiaPublicationList, CynthiaPublicationListTrait,
+};
+use crate::renders::{render_from_pgid, QueryParams, RenderrerResponse};
+use crate::tell::CynthiaColors;
+use crate::{sitemap, LockCallback, ServerContext};
+
+/// Result of a static export, used for reporting back to the user.
+pub(crate) struct ExportSummary {
+    pub(crate) written: usize,
+    pub(crate) failed: usize,
+}
+
+impl ExportSummary {
+    /// Writes `contents` to `target`, and counts and reports whether that worked.
+    fn write_output(&mut self, config_clone: &CynthiaConfClone, target: &Path, contents: &[u8]) {
+        match write_file(target, contents) {
+            Ok(_) => {
+                self.written += 1;
+                config_clone.tell(format!(
+                    "{}\t{}",
+                    "Wrote".color_ok_green(),
+                    target.display().to_string().color_lightblue()
+                ));
+            }
+            Err(e) => {
+                self.failed += 1;
+                error!("Could not write `{}`: {e}", target.display());
+            }
+        }
+    }
+}
+
+/// Renders all publications, the virtual tag and category lists, their feeds, the sitemap,
+/// `robots.txt` and the 404 page to `outdir`, and copies the assets folder next to them.
+pub(crate) async fn export(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    outdir: PathBuf,
+) -> Result<ExportSummary, String> {
+    let config_clone = server_context_mutex
+        .lock_callback(|a| a.config.clone())
+        .await;
+    let published = CynthiaPublicationList::load(server_context_mutex.clone()).await;
+    if !published.validate(config_clone.clone()) {
+        return Err(String::from(
+            "Incorrect publications found in publications.jsonc.",
+        ));
+    }
+    if let Err(e) = fs::create_dir_all(&outdir) {
+        return Err(format!(
+            "Could not create output folder `{}`: {e}",
+            outdir.display()
+        ));
+    }
+
+    // Every page id paired with the file it should be written to, and whether it can have more
+    // pages.
+    let mut targets: Vec<(String, PathBuf, bool)> = vec![];
+    // Redirects and aliases can't be rendered, static hosts get a page that redirects instead.
+    let mut redirects: Vec<(String, PathBuf)> = vec![];
+    for publication in published.iter() {
+        let id = publication.get_id();
+        let redirect = publication.get_redirect().map(|(location, _)| location);
+        match &redirect {
+            Some(location) => redirects.push((location.clone(), output_path(&outdir, &id))),
+            None => targets.push((id.clone(), output_path(&outdir, &id), true)),
+        }
+        for alias in publication.get_aliases() {
+            let location = redirect.clone().unwrap_or_else(|| url_path(&id));
+            redirects.push((location, output_path(&outdir, &alias)));
+        }
+    }
+    if let Some(notfound) = published.get_notfound(config_clone.clone()) {
+        // Most static hosts pick up a `404.html` in the root folder on their own.
+        targets.push((notfound.get_id(), outdir.join("404.html"), false));
+    }
+    for tag in published.tags() {
+        targets.push((
+            CynthiaPublication::virtual_tag_list(&tag).virtual_id(),
+            outdir.join("tag").join(&tag).join("index.html"),
+            true,
+        ));
+    }
+    for category in published.categories() {
+        targets.push((
+            CynthiaPublication::virtual_category_list(&category).virtual_id(),
+            outdir.join("category").join(&category).join("index.html"),
+            true,
+        ));
+    }
+    let mut years: Vec<i32> = vec![];
+    for month in published.archive(&config_clone.site) {
+        if !years.contains(&month.year) {
+            years.push(month.year);
+            targets.push((
+                CynthiaPublication::virtual_archive_list(month.year, None).virtual_id(),
+                outdir
+                    .join("archive")
+                    .join(month.year.to_string())
+                    .join("index.html"),
+                true,
+            ));
+        }
+        targets.push((
+            CynthiaPublication::virtual_archive_list(month.year, Some(month.month)).virtual_id(),
+            outdir
+                .join("archive")
+                .join(month.year.to_string())
+                .join(format!("{:02}", month.month))
+                .join("index.html"),
+            true,
+        ));
+    }
+    for author in published.authors() {
+        targets.push((
+            CynthiaPublication::virtual_author_list(&author).virtual_id(),
+            outdir
+                .join("author")
+                .join(publications::slug(&author))
+                .join("index.html"),
+            true,
+        ));
+    }
+
+    // Publications were added first, they take the place of the lists Cynthia makes on the same
+    // path.
+    let mut taken = HashSet::new();
+    targets.retain(|(_, target, _)| taken.insert(target.clone()));
+
+    let mut summary = ExportSummary {
+        written: 0,
+        failed: 0,
+    };
+    for (location, target) in redirects {
+        summary.write_output(&config_clone, &target, redirect_page(&location).as_bytes());
+    }
+    for (page_id, first_target, paginated) in targets {
+        // Postlists get a `page/{n}/index.html` next to their own for every further page, until
+        // the renderer runs out of pages.
+        for page in 1.. {
+            let target = match page {
+                1 => first_target.clone(),
+                n => first_target
+                    .with_file_name("page")
+                    .join(n.to_string())
+                    .join("index.html"),
+            };
+            match render_from_pgid(
+                page_id.clone(),
+                page,
+                QueryParams::new(),
+                server_context_mutex.clone(),
+            )
+            .await
+            {
+                RenderrerResponse::Ok(html) => {
+                    summary.write_output(&config_clone, &target, html.as_bytes())
+                }
+                RenderrerResponse::NotFound if page > 1 => break,
+                _ => {
+                    summary.failed += 1;
+                    error!("Could not render publication `{}`.", page_id);
+                    break;
+                }
+            }
+            if !paginated {
+                break;
+            }
+        }
+        // Feeds are written next to the first page of their postlist.
+        let Some(publication) = published.get_by_id(page_id.clone()).filter(|_| paginated) else {
+            continue;
+        };
+        for kind in FeedKind::ALL {
+            let Some(xml) = feeds::render_feed(&server_context_mutex, &publication, kind).await
+            else {
+                continue;
+            };
+            let target = first_target.with_file_name(kind.file_name());
+            summary.write_output(&config_clone, &target, xml.as_bytes());
+        }
+    }
+
+    // The sitemap, and its further parts if it is split up.
+    for part in 0.. {
+        let (xml, target) = match part {
+            0 => (
+                sitemap::render_sitemap(&server_context_mutex, None).await,
+                outdir.join("sitemap.xml"),
+            ),
+            n => (
+                sitemap::render_sitemap(&server_context_mutex, Some(n)).await,
+                outdir.join(format!("sitemap-{}.xml", n)),
+            ),
+        };
+        let Some(xml) = xml else {
+            break;
+        };
+        summary.write_output(&config_clone, &target, xml.as_bytes());
+    }
+
+    if let Some(txt) = sitemap::render_robots_txt(&config_clone) {
+        summary.write_output(&config_clone, &outdir.join("robots.txt"), txt.as_bytes());
+    }
+
+    let assets = std::env::current_dir()
+        .unwrap()
+        .join("cynthiaFiles/assets/");
+    if assets.exists() {
+        let assets_target = outdir.join("assets");
+        if let Err(e) = fs::create_dir_all(&assets_target) {
+            return Err(format!(
+                "Could not create output folder `{}`: {e}",
+                assets_target.display()
+            ));
+        }
+        let options = fs_extra::dir::CopyOptions::new()
+            .overwrite(true)
+            .content_only(true);
+        if let Err(e) = fs_extra::dir::copy(&assets, &assets_target, &options) {
+            return Err(format!("Could not copy assets: {e}"));
+        }
+        config_clone.tell(format!(
+            "{}\t{}",
+            "Copied".color_ok_green(),
+            assets_target.display().to_string().color_lightblue()
+        ));
+    }
+    Ok(summary)
+}
+
+/// Maps a publication id to the file it is exported to. Every publication gets its own folder with
+/// an `index.html`, so that `/{id}` keeps working on static hosts.
+fn output_path(outdir: &Path, id: &str) -> PathBuf {
+    match id.trim_matches('/') {
+        "" | "root" => outdir.join("index.html"),
+        id => outdir.join(id).join("index.html"),
+    }
+}
+
+/// A page that sends the browser on to `location`, for hosts that can't send redirects.
+fn redirect_page(location: &str) -> String {
+    let location = location
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;");
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><meta http-equiv=\"refresh\" content=\"0; url={location}\"><link rel=\"canonical\" href=\"{location}\"></head><body><a href=\"{location}\">{location}</a></body></html>\n"
+    )
+}
+
+fn write_file(target: &Path, contents: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(target, contents)
+}
@@ -86,8 +86,36 @@ pub(crate) enum EPSRequestBody {
         uri: String,
         headers: Vec<(String, String)>, // Name, Value
         method: String,
+        query: Vec<(String, String)>, // Name, Value
+        /// The raw request body, base64 encoded if `body_base64` is set.
+        body: Option<String>,
+        body_base64: bool,
+        form: Vec<(String, String)>, // Name, Value
+        files: Vec<UploadedFile>,
     },
 }
+impl EPSRequestBody {
+    /// Names the kind of request, for metrics.
+    #[cfg_attr(not(feature = "js_runtime"), allow(dead_code))]
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            EPSRequestBody::Close => "close",
+            EPSRequestBody::Test { .. } => "test",
+            EPSRequestBody::ContentRenderRequest { .. } => "content_render",
+            EPSRequestBody::PostlistRenderRequest { .. } => "postlist_render",
+            EPSRequestBody::WebRequest { .. } => "web_request",
+        }
+    }
+}
+/// A file uploaded in a multipart form. Plugins can read it from `path` while handling the request.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct UploadedFile {
+    pub(crate) field: String,
+    pub(crate) filename: Option<String>,
+    pub(crate) content_type: Option<String>,
+    pub(crate) path: String,
+    pub(crate) size: u64,
+}
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct EPSResponse {
     id: EPSCommunicationsID,
@@ -100,6 +128,16 @@ pub(crate) enum EPSResponseBody {
     WebResponse {
         append_headers: Vec<(String, String)>,
         response_body: String,
+        /// Defaults to `200 OK`.
+        #[serde(default)]
+        status: Option<u16>,
+        /// Whether `response_body` is base64 encoded binary data.
+        #[serde(default)]
+        body_base64: bool,
+        /// A file to stream as the body instead of `response_body`, for responses too large to
+        /// send over the plugin connection.
+        #[serde(default)]
+        body_file: Option<String>,
     },
     OkString {
         value: String,
@@ -287,6 +325,10 @@ fn new_proc(
                         o.split("debug: ").collect::<Vec<&str>>()[1]
                     );
                 } else if o.starts_with("error: ") {
+                    let ctx = ctx_clone.clone();
+                    rt.spawn(async move {
+                        ctx.lock().await.metrics.count_plugin_error("runtime");
+                    });
                     error!(
                         "[JsPluginRuntime]: {}",
                         o.split("error: ").collect::<Vec<&str>>()[1]
@@ -374,6 +416,8 @@ pub(crate) async fn contact_eps(
         let server_context = server_context_mutex.lock().await;
         server_context.external_plugin_server.sender.clone()
     };
+    let kind = req.kind();
+    let start = std::time::Instant::now();
     match eps_r
         .send(EPSRequest {
             id: random_id,
@@ -421,6 +465,10 @@ pub(crate) async fn contact_eps(
                                 .external_plugin_server
                                 .unreturned_ids
                                 .retain(|a| a != &random_id);
+                            server_context.metrics.observe_eps(kind, start.elapsed());
+                            if let EPSResponseBody::Error { .. } = p {
+                                server_context.metrics.count_plugin_error(kind);
+                            }
                             return p;
                         }
                     } else {
@@ -0,0 +1,447 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+
+//! RSS 2.0, Atom and JSON Feed 1.1 feeds of postlists, served next to the list itself:
+//! `/posts/feed.xml`, `/posts/atom.xml` and `/posts/feed.json` for the postlist on `/posts`.
+
+use std::sync::Arc;
+
+use actix_web::web::Data;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::config::CynthiaConfClone;
+use crate::publications::{
+    url_path, CynthiaPublication, CynthiaPublicationList, CynthiaPublicationListTrait,
+    PostPublication,
+};
+use crate::{renders, LockCallback, ServerContext};
+
+/// Feeds only hold the newest posts of a list.
+const FEED_LENGTH: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum FeedKind {
+    Rss,
+    Atom,
+    Json,
+}
+
+impl FeedKind {
+    pub(crate) const ALL: [FeedKind; 3] = [FeedKind::Rss, FeedKind::Atom, FeedKind::Json];
+
+    /// The file name a feed is served under, next to its postlist.
+    pub(crate) fn file_name(&self) -> &'static str {
+        match self {
+            FeedKind::Rss => "feed.xml",
+            FeedKind::Atom => "atom.xml",
+            FeedKind::Json => "feed.json",
+        }
+    }
+
+    pub(crate) fn content_type(&self) -> &'static str {
+        match self {
+            FeedKind::Rss => "application/rss+xml; charset=utf-8",
+            FeedKind::Atom => "application/atom+xml; charset=utf-8",
+            FeedKind::Json => "application/feed+json; charset=utf-8",
+        }
+    }
+
+    fn enabled(&self, config: &CynthiaConfClone) -> bool {
+        match self {
+            FeedKind::Rss => config.site.meta.enable_rss,
+            FeedKind::Atom => config.site.meta.enable_atom,
+            FeedKind::Json => config.site.meta.enable_json_feed,
+        }
+    }
+}
+
+/// The feed URLs of a postlist, for templates to link to. Feeds that are disabled are left out.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct FeedLinks {
+    pub(crate) rss: Option<String>,
+    pub(crate) atom: Option<String>,
+    pub(crate) json: Option<String>,
+}
+
+impl FeedLinks {
+    pub(crate) fn for_publication(
+        publication: &CynthiaPublication,
+        config: &CynthiaConfClone,
+    ) -> Self {
+        if !has_feeds(publication) {
+            return FeedLinks::default();
+        }
+        let link = |kind: FeedKind| {
+            kind.enabled(config)
+                .then(|| feed_path(&publication.get_url_path(), kind))
+        };
+        FeedLinks {
+            rss: link(FeedKind::Rss),
+            atom: link(FeedKind::Atom),
+            json: link(FeedKind::Json),
+        }
+    }
+}
+
+/// Splits `{id}/feed.xml`, `{id}/atom.xml` or `{id}/feed.json` into the id and the kind of feed.
+/// A bare `feed.xml` is a feed of the root, with an empty id.
+pub(crate) fn split_feed(path: &str) -> (&str, Option<FeedKind>) {
+    for kind in FeedKind::ALL {
+        match path.strip_suffix(kind.file_name()) {
+            Some("") => return ("", Some(kind)),
+            Some(base) => {
+                if let Some(base) = base.strip_suffix('/') {
+                    return (base, Some(kind));
+                }
+            }
+            None => {}
+        }
+    }
+    (path, None)
+}
+
+/// The path of a feed of the postlist on `url_path`.
+pub(crate) fn feed_path(url_path: &str, kind: FeedKind) -> String {
+    format!("{}/{}", url_path.trim_end_matches('/'), kind.file_name())
+}
+
+/// Only postlists have feeds. Search results don't, as they change with every query.
+pub(crate) fn has_feeds(publication: &CynthiaPublication) -> bool {
+    matches!(publication, CynthiaPublication::PostList { .. })
+        && !publication.get_url_path().contains('?')
+}
+
+/// The posts a feed holds: the newest [`FEED_LENGTH`], whatever order the postlist itself uses.
+/// Tag, category and author lists keep the order of the publication file, and `Oldest` lists
+/// start with the oldest post.
+fn newest(mut posts: Vec<PostPublication>) -> Vec<PostPublication> {
+    posts.sort_by_key(|p| std::cmp::Reverse(p.dates.published));
+    posts.truncate(FEED_LENGTH);
+    posts
+}
+
+/// Renders a feed of a postlist. Returns `None` if the publication has no feeds, or this kind is
+/// disabled.
+pub(crate) async fn render_feed(
+    server_context_mutex: &Data<Arc<Mutex<ServerContext>>>,
+    publication: &CynthiaPublication,
+    kind: FeedKind,
+) -> Option<String> {
+    let config = server_context_mutex
+        .lock_callback(|a| a.config.clone())
+        .await;
+    if !has_feeds(publication) || !kind.enabled(&config) {
+        return None;
+    }
+    let CynthiaPublication::PostList {
+        title,
+        short,
+        filter,
+        ..
+    } = publication
+    else {
+        return None;
+    };
+    let published = CynthiaPublicationList::load(server_context_mutex.clone()).await;
+    let posts = newest(renders::list_posts(filter.clone(), &published, server_context_mutex).await);
+    let mut items = Vec::with_capacity(posts.len());
+    for post in posts {
+        let content = if config.site.meta.feed_content {
+            renders::render_content(post.postcontent.clone()).await
+        } else {
+            None
+        };
+        items.push((post, content));
+    }
+    let base = config.site.site_baseurl.trim_end_matches('/');
+    let title = match config.site.og_sitename.as_str() {
+        "" => title.clone(),
+        sitename => format!("{} - {}", title, sitename),
+    };
+    let channel = Channel {
+        title,
+        description: short.clone(),
+        link: format!("{}{}", base, publication.get_url_path()),
+        self_link: format!("{}{}", base, feed_path(&publication.get_url_path(), kind)),
+        base: base.to_string(),
+        author: config.site.og_sitename.clone(),
+    };
+    Some(match kind {
+        FeedKind::Rss => rss(&channel, &items),
+        FeedKind::Atom => atom(&channel, &items),
+        FeedKind::Json => json(&channel, &items),
+    })
+}
+
+/// What a feed says about the list itself.
+struct Channel {
+    title: String,
+    description: Option<String>,
+    link: String,
+    self_link: String,
+    /// `site_baseurl`, without a trailing slash, that post URLs are made absolute with.
+    base: String,
+    /// Used for posts without an author in Atom feeds, which require one.
+    author: String,
+}
+
+fn rss(channel: &Channel, items: &[(PostPublication, Option<String>)]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\" xmlns:content=\"http://purl.org/rss/1.0/modules/content/\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n<channel>\n");
+    xml.push_str(&format!(
+        "<title>{}</title>\n<link>{}</link>\n<description>{}</description>\n<atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n<generator>Cynthia {}</generator>\n",
+        escape(&channel.title),
+        escape(&channel.link),
+        escape(channel.description.as_deref().unwrap_or(&channel.title)),
+        escape(&channel.self_link),
+        env!("CARGO_PKG_VERSION")
+    ));
+    if let Some(newest) = items.iter().map(|(p, _)| p.dates.altered).max() {
+        xml.push_str(&format!(
+            "<lastBuildDate>{}</lastBuildDate>\n",
+            date(newest).to_rfc2822()
+        ));
+    }
+    for (post, content) in items {
+        let link = format!("{}{}", channel.base, url_path(&post.id));
+        xml.push_str("<item>\n");
+        xml.push_str(&format!(
+            "<title>{}</title>\n<link>{}</link>\n<guid isPermaLink=\"true\">{}</guid>\n<pubDate>{}</pubDate>\n",
+            escape(&post.title),
+            escape(&link),
+            escape(&link),
+            date(post.dates.published).to_rfc2822()
+        ));
+        if let Some(short) = &post.short {
+            xml.push_str(&format!("<description>{}</description>\n", escape(short)));
+        }
+        if let Some(content) = content {
+            xml.push_str(&format!(
+                "<content:encoded>{}</content:encoded>\n",
+                escape(content)
+            ));
+        }
+        if let Some(name) = post.author.as_ref().and_then(|a| a.name.as_ref()) {
+            xml.push_str(&format!("<dc:creator>{}</dc:creator>\n", escape(name)));
+        }
+        for category in post.category.iter().chain(post.tags.iter()) {
+            xml.push_str(&format!("<category>{}</category>\n", escape(category)));
+        }
+        xml.push_str("</item>\n");
+    }
+    xml.push_str("</channel>\n</rss>\n");
+    xml
+}
+
+fn atom(channel: &Channel, items: &[(PostPublication, Option<String>)]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    let updated = items
+        .iter()
+        .map(|(p, _)| p.dates.altered)
+        .max()
+        .unwrap_or(0);
+    xml.push_str(&format!(
+        "<title>{}</title>\n<id>{}</id>\n<link href=\"{}\" rel=\"alternate\" type=\"text/html\"/>\n<link href=\"{}\" rel=\"self\" type=\"application/atom+xml\"/>\n<updated>{}</updated>\n<generator version=\"{}\">Cynthia</generator>\n",
+        escape(&channel.title),
+        escape(&channel.link),
+        escape(&channel.link),
+        escape(&channel.self_link),
+        date(updated).to_rfc3339(),
+        env!("CARGO_PKG_VERSION")
+    ));
+    if let Some(description) = &channel.description {
+        xml.push_str(&format!("<subtitle>{}</subtitle>\n", escape(description)));
+    }
+    for (post, content) in items {
+        let link = format!("{}{}", channel.base, url_path(&post.id));
+        let author = post
+            .author
+            .as_ref()
+            .and_then(|a| a.name.clone())
+            .unwrap_or_else(|| channel.author.clone());
+        xml.push_str("<entry>\n");
+        xml.push_str(&format!(
+            "<title>{}</title>\n<id>{}</id>\n<link href=\"{}\" rel=\"alternate\" type=\"text/html\"/>\n<published>{}</published>\n<updated>{}</updated>\n<author><name>{}</name></author>\n",
+            escape(&post.title),
+            escape(&link),
+            escape(&link),
+            date(post.dates.published).to_rfc3339(),
+            date(post.dates.altered).to_rfc3339(),
+            escape(&author)
+        ));
+        if let Some(short) = &post.short {
+            xml.push_str(&format!("<summary>{}</summary>\n", escape(short)));
+        }
+        if let Some(content) = content {
+            xml.push_str(&format!(
+                "<content type=\"html\">{}</content>\n",
+                escape(content)
+            ));
+        }
+        for category in post.category.iter().chain(post.tags.iter()) {
+            xml.push_str(&format!("<category term=\"{}\"/>\n", escape(category)));
+        }
+        xml.push_str("</entry>\n");
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+fn json(channel: &Channel, items: &[(PostPublication, Option<String>)]) -> String {
+    let items: Vec<serde_json::Value> = items
+        .iter()
+        .map(|(post, content)| {
+            let link = format!("{}{}", channel.base, url_path(&post.id));
+            let mut item = serde_json::json!({
+                "id": link,
+                "url": link,
+                "title": post.title,
+                "date_published": date(post.dates.published).to_rfc3339(),
+                "date_modified": date(post.dates.altered).to_rfc3339(),
+                "tags": post.category.iter().chain(post.tags.iter()).collect::<Vec<_>>(),
+            });
+            // JSON Feed requires either `content_html` or `content_text`, the description stands in
+            // for the content when that is left out.
+            match content {
+                Some(content) => item["content_html"] = content.clone().into(),
+                None => {
+                    item["content_text"] = post.short.clone().unwrap_or_default().into();
+                }
+            }
+            if let Some(short) = &post.short {
+                item["summary"] = short.clone().into();
+            }
+            if let Some(thumbnail) = &post.thumbnail {
+                item["image"] = absolute(&channel.base, thumbnail).into();
+            }
+            if let Some(author) = &post.author {
+                let mut a = serde_json::json!({});
+                if let Some(name) = &author.name {
+                    a["name"] = name.clone().into();
+                }
+                if let Some(link) = &author.link {
+                    a["url"] = absolute(&channel.base, link).into();
+                }
+                if let Some(thumbnail) = &author.thumbnail {
+                    a["avatar"] = absolute(&channel.base, thumbnail).into();
+                }
+                item["authors"] = serde_json::json!([a]);
+            }
+            item
+        })
+        .collect();
+    let mut feed = serde_json::json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": channel.title,
+        "home_page_url": channel.link,
+        "feed_url": channel.self_link,
+        "items": items,
+    });
+    if let Some(description) = &channel.description {
+        feed["description"] = description.clone().into();
+    }
+    if !channel.author.is_empty() {
+        feed["authors"] = serde_json::json!([{ "name": channel.author }]);
+    }
+    serde_json::to_string_pretty(&feed).unwrap_or_default()
+}
+
+/// Makes a link that is relative to the site root absolute. Links that are absolute already are left
+/// as they are.
+fn absolute(base: &str, link: &str) -> String {
+    if link.starts_with('/') && !link.starts_with("//") {
+        format!("{}{}", base, link)
+    } else {
+        link.to_string()
+    }
+}
+
+fn date(unix: u64) -> DateTime<Utc> {
+    DateTime::from_timestamp(unix as i64, 0).unwrap_or_default()
+}
+
+/// Escapes text for use in XML, both in elements and in attributes.
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Site;
+    use crate::publications::{CynthiaPostList, PostListFilter, PostLists};
+
+    fn post(id: &str, published: u64, tags: &[&str]) -> PostPublication {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "title": id,
+            "dates": { "altered": published, "published": published },
+            "tags": tags,
+            "postcontent": { "Inline": { "as": "Html", "value": "" } },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn feed_paths() {
+        assert_eq!(feed_path("/posts", FeedKind::Rss), "/posts/feed.xml");
+        assert_eq!(feed_path("/posts/", FeedKind::Atom), "/posts/atom.xml");
+        assert_eq!(
+            feed_path("/tag/rust", FeedKind::Json),
+            "/tag/rust/feed.json"
+        );
+        // The root postlist has its feeds in the root folder.
+        assert_eq!(feed_path("/", FeedKind::Rss), "/feed.xml");
+        assert_eq!(split_feed("feed.xml"), ("", Some(FeedKind::Rss)));
+        // Routes get the path without its leading slash, or without their own prefix.
+        for kind in FeedKind::ALL {
+            for list in ["/", "/posts", "/blog/posts/", "/tag/rust"] {
+                let path = feed_path(list, kind);
+                assert_eq!(
+                    split_feed(path.trim_start_matches('/')),
+                    (list.trim_matches('/'), Some(kind))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn not_feed_paths() {
+        assert_eq!(split_feed("posts"), ("posts", None));
+        assert_eq!(split_feed("posts/"), ("posts/", None));
+        assert_eq!(split_feed("posts/myfeed.xml"), ("posts/myfeed.xml", None));
+        assert_eq!(split_feed("posts/feed.xml/"), ("posts/feed.xml/", None));
+        assert_eq!(split_feed("myfeed.xml"), ("myfeed.xml", None));
+    }
+
+    #[test]
+    fn feeds_hold_the_newest_posts() {
+        // In the publication file, the posts aren't in date order.
+        let posts: CynthiaPostList = vec![
+            post("2020", 1_577_836_800, &["rust"]),
+            post("2024", 1_704_067_200, &["rust"]),
+            post("untagged", 1_800_000_000, &[]),
+            post("2022", 1_640_995_200, &["rust"]),
+        ];
+        let tagged = posts.filter(PostListFilter::Tag("rust".to_string()), &Site::default());
+        let ids: Vec<String> = newest(tagged).into_iter().map(|p| p.id).collect();
+        assert_eq!(ids, ["2024", "2022", "2020"]);
+
+        let many: CynthiaPostList = (0..FEED_LENGTH as u64 + 5)
+            .map(|n| post(&n.to_string(), n, &[]))
+            .collect();
+        let feed = newest(many.filter(PostListFilter::Oldest, &Site::default()));
+        assert_eq!(feed.len(), FEED_LENGTH);
+        assert_eq!(feed[0].id, (FEED_LENGTH + 4).to_string());
+    }
+}
@@ -5,7 +5,7 @@
  */
 use crate::config::CynthiaConfClone;
 use crate::ServerContext;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tokio::sync::MutexGuard;
 
 fn get_lifetime(pr: FilePriority, config_clone: CynthiaConfClone) -> u64 {
@@ -95,3 +95,30 @@ pub(crate) fn fs_get(
     };
     Ok(file)
 }
+
+/// Determines the `Content-Type` for a file, by its extension first and by sniffing its contents
+/// when the extension is unknown.
+pub(crate) fn content_type(path: &Path, contents: &[u8]) -> String {
+    let mime = match mime_guess::from_path(path).first() {
+        Some(m) => m,
+        None => match infer::get(contents) {
+            Some(kind) => kind
+                .mime_type()
+                .parse()
+                .unwrap_or(mime::APPLICATION_OCTET_STREAM),
+            None if std::str::from_utf8(contents).is_ok() => mime::TEXT_PLAIN,
+            None => mime::APPLICATION_OCTET_STREAM,
+        },
+    };
+    // Textual types are served with an explicit charset, like the rendered pages are.
+    let textual = mime.type_() == mime::TEXT
+        || mime.subtype() == mime::JAVASCRIPT
+        || mime.subtype() == mime::JSON
+        || mime.suffix() == Some(mime::XML)
+        || mime.subtype() == mime::XML;
+    if textual && mime.get_param(mime::CHARSET).is_none() {
+        format!("{}; charset=utf-8", mime.essence_str())
+    } else {
+        mime.to_string()
+    }
+}
@@ -5,9 +5,10 @@
  */
 
 use boa_engine::{Context, JsResult, JsValue, Source};
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 
-#[derive(Serialize, Deserialize)]
+#[cfg(test)]
+#[derive(serde::Serialize, Deserialize)]
 struct TestObject {
     a: u8,
     b: u8,
@@ -4,13 +4,18 @@
  * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
  */
 
-use actix_web::web::Data;
+use actix_web::dev::Service;
+use actix_web::web::{self, Data};
 use actix_web::{App, HttpServer};
 use futures::join;
 use log::LevelFilter;
 use log::{debug, error};
 use log::{info, trace};
-use requestresponse::{assets_with_cache, category, post, serve, tags};
+use requestresponse::api::{api_categories, api_publication, api_publications, api_tags};
+use requestresponse::{
+    archive, assets_with_cache, author, category, dispatch, robots_txt, search_json, search_page,
+    serve, sitemap_xml, tags,
+};
 use simplelog::{ColorChoice, CombinedLogger, TermLogger, TerminalMode, WriteLogger};
 use std::fs::File;
 use std::path::PathBuf;
@@ -19,6 +24,7 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use std::{fs, process};
 use tell::{CynthiaColors, CynthiaStyles};
 use tokio::spawn;
+use tokio::sync::mpsc::Receiver;
 use tokio::sync::{Mutex, MutexGuard};
 
 use crate::cache::CynthiaCache;
@@ -26,15 +32,22 @@ use crate::config::{CynthiaConf, CynthiaConfig, SceneCollectionTrait};
 use crate::externalpluginservers::EPSRequest;
 use crate::tell::horizline;
 
+mod accesslog;
 mod cache;
 mod config;
+mod export;
 mod externalpluginservers;
+mod feeds;
 mod files;
 mod helpers;
 mod jsrun;
+mod metrics;
 mod publications;
 mod renders;
 mod requestresponse;
+mod search;
+mod sitemap;
+mod tls;
 
 struct LogSets {
     pub file_loglevel: LevelFilter,
@@ -49,6 +62,9 @@ struct ServerContext {
     cache: CynthiaCache,
     request_count: u64,
     start_time: u128,
+    search_index: search::SearchIndex,
+    metrics: metrics::Registry,
+    access_log: Option<accesslog::AccessLogSink>,
 
     #[cfg(feature = "js_runtime")]
     external_plugin_server: EPSCommunicationData,
@@ -142,6 +158,12 @@ async fn main() {
                 "start".style_bold().color_yellow(),
                 ": Starts the server.".color_lime()
             );
+            println!(
+                "\t{}{}\n\t\t{}",
+                "build [output folder]".style_bold().color_yellow(),
+                ": Renders the site to static files.".color_lime(),
+                "If no output folder is specified, `./dist` is used.".style_clear()
+            );
             println!(
                 "\t{}{}\n\t\t{}",
                 "convert [format] <-k>".style_bold().color_yellow(),
@@ -169,6 +191,7 @@ async fn main() {
             process::exit(0);
         }
         "start" => start().await,
+        "build" => build().await,
         "convert" => {
             if args.len() < 3 {
                 eprintln!(
@@ -263,8 +286,8 @@ async fn interactive_initialiser() {
                     process::exit(1);
                 }
             }
-            if old_config.is_some() {
-                let old_config_path = match old_config.unwrap() {
+            if let Some(old_config) = old_config {
+                let old_config_path = match old_config {
                     config::actions::ConfigLocations::Js(_) => cd.join("CynthiaConfig.js"),
                     config::actions::ConfigLocations::Dhall(_) => cd.join("Cynthia.dhall"),
                     config::actions::ConfigLocations::Toml(_) => cd.join("Cynthia.toml"),
@@ -513,11 +536,13 @@ async fn interactive_initialiser() {
     }
 }
 
-async fn start() {
+/// Loads and validates the configuration, sets up logging and the temp folder and returns a fresh
+/// server context, together with the receiving end of the channel to the external plugin server.
+fn init_server_context() -> (ServerContext, Receiver<EPSRequest>) {
     let cd = std::env::current_dir().unwrap();
     let config = config::actions::load_config();
     // Validate the configuration
-    if config.port == 0 {
+    if config.port == 0 && config.listeners.is_empty() {
         eprintln!(
             "{} Could not set port to 0! Please set it to a valid port.",
             "error:".color_red()
@@ -527,6 +552,14 @@ async fn start() {
     if config.logs.is_none() {
         eprintln!("No log configuration found, using defaults");
     }
+    if config.site.utc_offset(0).is_none() {
+        eprintln!(
+            "{} Could not read timezone `{}`! Please use `utc`, `local` or an offset like `+02:00`.",
+            "error:".color_red(),
+            config.site.timezone
+        );
+        process::exit(1);
+    }
     // Validate scenes
     if !config.scenes.validate() {
         eprintln!(
@@ -601,6 +634,10 @@ async fn start() {
         cache: vec![],
         request_count: 0,
         start_time: 0,
+        search_index: search::SearchIndex::default(),
+        metrics: metrics::Registry::default(),
+        // Opened by `start`, so other commands don't create the file.
+        access_log: None,
 
         #[cfg(feature = "js_runtime")]
         external_plugin_server: EPSCommunicationData::new(_to_eps_s),
@@ -625,38 +662,180 @@ async fn start() {
             process::exit(1);
         }
     }
+    (server_context, to_eps_r)
+}
+
+async fn start() {
+    let (mut server_context, to_eps_r) = init_server_context();
+    if let Some(a) = server_context
+        .config
+        .logs
+        .as_ref()
+        .and_then(|l| l.access_log.as_ref())
+    {
+        let path = std::env::current_dir().unwrap().join(&a.path);
+        match accesslog::AccessLogSink::open(&path, a.format) {
+            Ok(sink) => server_context.access_log = Some(sink),
+            Err(e) => {
+                eprintln!(
+                    "{} Could not open access log `{}`: {}",
+                    "error:".color_red(),
+                    a.path,
+                    e
+                );
+                process::exit(1);
+            }
+        }
+    }
+    let listeners = server_context.config.get_listeners();
+    let tls_config = server_context.config.tls.clone();
+    let metrics_config = server_context.config.metrics.clone();
+    let (rustls_config, cert_resolver) = match &tls_config {
+        Some(t) => match tls::server_config(t) {
+            Ok((c, r)) => (Some(c), Some(r)),
+            Err(e) => {
+                error!("Could not set up TLS: {}", e);
+                process::exit(1);
+            }
+        },
+        None => (None, None),
+    };
+    // Redirects go to the port of the first TCP listener.
+    let https_port = listeners
+        .iter()
+        .find_map(|l| match l.get_address() {
+            config::ListenAddress::Tcp(a) => a.rsplit_once(':').and_then(|(_, p)| p.parse().ok()),
+            config::ListenAddress::Unix(_) => None,
+        })
+        .unwrap_or(443);
     let server_context_arc_mutex: Arc<Mutex<ServerContext>> = Arc::new(Mutex::new(server_context));
     let server_context_data: Data<Arc<Mutex<ServerContext>>> =
         Data::new(server_context_arc_mutex.clone());
-    let main_server = match HttpServer::new(move || {
+    let mut main_server = HttpServer::new(move || {
         App::new()
+            .wrap_fn(|req, srv| {
+                let start = std::time::Instant::now();
+                let response = srv.call(req);
+                async move {
+                    let response = response.await?;
+                    metrics::count(&response).await;
+                    accesslog::write(&response, start.elapsed()).await;
+                    Ok(response)
+                }
+            })
             .service(tags)
             .service(category)
+            .service(author)
+            .service(archive)
+            .service(search_json)
+            .service(search_page)
+            .service(sitemap_xml)
+            .service(robots_txt)
+            .service(api_publications)
+            .service(api_publication)
+            .service(api_tags)
+            .service(api_categories)
             .service(assets_with_cache)
             .service(serve)
-            .service(post)
+            .default_service(web::to(dispatch))
             .app_data(server_context_data.clone())
-    })
-    .bind(("localhost", config.port))
-    {
-        Ok(o) => {
-            println!("Running on http://localhost:{}", config.port);
-            o
-        }
-        Err(s) => {
-            error!(
-                "Could not bind to port {}, error message: {}",
-                config.port, s
-            );
-            process::exit(1);
-        }
+    });
+    for listener in listeners {
+        let permissions = match listener.get_permissions() {
+            Ok(p) => p,
+            Err(e) => {
+                error!("Invalid listener {}: {}", listener.address, e);
+                process::exit(1);
+            }
+        };
+        let bound = match listener.get_address() {
+            config::ListenAddress::Tcp(address) => match &rustls_config {
+                Some(c) => main_server.bind_rustls_0_23(address, c.clone()),
+                None => main_server.bind(address),
+            },
+            #[cfg(unix)]
+            config::ListenAddress::Unix(path) => {
+                // A socket file left behind by an earlier run would make binding fail.
+                let _ = fs::remove_file(&path);
+                main_server.bind_uds(&path).and_then(|o| {
+                    use std::os::unix::fs::PermissionsExt;
+                    if let Some(mode) = permissions {
+                        fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+                    }
+                    Ok(o)
+                })
+            }
+            #[cfg(not(unix))]
+            config::ListenAddress::Unix(_) => {
+                error!("Unix domain sockets are not supported on this platform.");
+                process::exit(1);
+            }
+        };
+        main_server = match bound {
+            Ok(o) => {
+                println!("Running on {}", listener.describe(rustls_config.is_some()));
+                o
+            }
+            Err(s) => {
+                error!(
+                    "Could not bind to {}, error message: {}",
+                    listener.address, s
+                );
+                process::exit(1);
+            }
+        };
     }
-    .run();
+    let main_server = main_server.run();
     let _ = join!(
         main_server,
         close(server_context_arc_mutex.clone()),
         cache_manager(server_context_arc_mutex.clone()),
         start_timer(server_context_arc_mutex.clone()),
+        search::indexer(server_context_arc_mutex.clone()),
+        tls::redirect_server(tls_config.clone(), https_port),
+        tls::watch(cert_resolver, tls_config),
+        metrics::server(metrics_config, server_context_arc_mutex.clone()),
+        externalpluginservers::main(server_context_arc_mutex.clone(), to_eps_r)
+    );
+}
+async fn build() {
+    let args: Vec<String> = std::env::args().collect();
+    let outdir = std::env::current_dir()
+        .unwrap()
+        .join(args.get(2).map(|a| a.as_str()).unwrap_or("dist"));
+    let (server_context, to_eps_r) = init_server_context();
+    let server_context_arc_mutex: Arc<Mutex<ServerContext>> = Arc::new(Mutex::new(server_context));
+    let server_context_data: Data<Arc<Mutex<ServerContext>>> =
+        Data::new(server_context_arc_mutex.clone());
+    let _ = join!(
+        async {
+            match export::export(server_context_data, outdir.clone()).await {
+                Ok(summary) => {
+                    println!(
+                        "{} Exported {} files to {}.",
+                        "Done:".color_green(),
+                        summary.written,
+                        outdir
+                            .to_string_lossy()
+                            .replace("\\\\?\\", "")
+                            .color_bright_cyan()
+                    );
+                    if summary.failed > 0 {
+                        eprintln!(
+                            "{} {} publication(s) could not be exported, see the log for details.",
+                            "error:".color_red(),
+                            summary.failed
+                        );
+                        process::exit(1);
+                    }
+                    process::exit(0);
+                }
+                Err(e) => {
+                    eprintln!("{} {}", "error:".color_red(), e);
+                    process::exit(1);
+                }
+            }
+        },
         externalpluginservers::main(server_context_arc_mutex.clone(), to_eps_r)
     );
 }
@@ -669,7 +848,11 @@ async fn start_timer(server_context_mutex: Arc<Mutex<ServerContext>>) {
 }
 async fn close(server_context_mutex: Arc<Mutex<ServerContext>>) {
     let _ = tokio::signal::ctrl_c().await;
-    let server_context: MutexGuard<ServerContext> = server_context_mutex.lock().await;
+    let mut server_context: MutexGuard<ServerContext> = server_context_mutex.lock().await;
+    // Write out what's left of the access log before exiting.
+    if let Some(access_log) = server_context.access_log.take() {
+        access_log.close().await;
+    }
     // Basically now that we block the main thread, we have all the time lol
     // let _ = server_context
     //     .external_plugin_server
@@ -0,0 +1,383 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+
+//! Prometheus metrics, served on `/metrics` of the listener set in `[metrics]`.
+//!
+//! The counters live in [`ServerContext`], and are written out in the Prometheus text format when
+//! scraped. The metrics listener is separate from the site's own listeners, so it can be kept
+//! private.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::dev::ServiceResponse;
+use actix_web::http::{header, Method};
+use actix_web::web::{self, Data};
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer};
+use log::error;
+use tokio::sync::Mutex;
+
+use crate::config::{ListenAddress, Metrics};
+use crate::{LockCallback, ServerContext};
+
+/// Upper bounds (in seconds) of the latency histogram buckets.
+const BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+#[derive(Debug, Default)]
+pub(crate) struct Registry {
+    /// Answered requests, by route kind and status code.
+    requests: BTreeMap<(&'static str, u16), u64>,
+    /// How long rendering a publication took, by the kind of publication.
+    renders: BTreeMap<&'static str, Histogram>,
+    cache_hits: u64,
+    cache_misses: u64,
+    /// Round trips to the external plugin server, by the kind of request.
+    eps: BTreeMap<&'static str, Histogram>,
+    /// Errors reported by plugins, by the kind of request, or `runtime` for errors the plugin
+    /// runtime logged outside of a request.
+    plugin_errors: BTreeMap<&'static str, u64>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations per bucket, not cumulative. The last one counts observations above every bound.
+    buckets: [u64; BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = BUCKETS
+            .iter()
+            .position(|le| seconds <= *le)
+            .unwrap_or(BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.sum += seconds;
+        self.count += 1;
+    }
+    fn write(&self, out: &mut String, name: &str, label: &str, value: &str) {
+        let mut cumulative = 0;
+        for (i, count) in self.buckets.iter().enumerate() {
+            cumulative += count;
+            let le = BUCKETS
+                .get(i)
+                .map(|le| le.to_string())
+                .unwrap_or_else(|| String::from("+Inf"));
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{label}=\"{value}\",le=\"{le}\"}} {cumulative}"
+            );
+        }
+        let _ = writeln!(out, "{name}_sum{{{label}=\"{value}\"}} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{{{label}=\"{value}\"}} {}", self.count);
+    }
+}
+
+impl Registry {
+    pub(crate) fn count_request(&mut self, route: &'static str, status: u16) {
+        *self.requests.entry((route, status)).or_default() += 1;
+    }
+    pub(crate) fn observe_render(&mut self, kind: &'static str, duration: Duration) {
+        self.renders.entry(kind).or_default().observe(duration);
+    }
+    pub(crate) fn count_cache(&mut self, hit: bool) {
+        match hit {
+            true => self.cache_hits += 1,
+            false => self.cache_misses += 1,
+        }
+    }
+    pub(crate) fn observe_eps(&mut self, request: &'static str, duration: Duration) {
+        self.eps.entry(request).or_default().observe(duration);
+    }
+    pub(crate) fn count_plugin_error(&mut self, request: &'static str) {
+        *self.plugin_errors.entry(request).or_default() += 1;
+    }
+}
+
+impl ServerContext {
+    /// Writes every metric in the Prometheus text format.
+    fn render_metrics(&self) -> String {
+        let m = &self.metrics;
+        let mut out = String::new();
+        family(
+            &mut out,
+            "cynthia_requests_total",
+            "counter",
+            "Requests answered, by route kind and status code.",
+        );
+        for ((route, status), count) in &m.requests {
+            let _ = writeln!(
+                out,
+                "cynthia_requests_total{{route=\"{route}\",status=\"{status}\"}} {count}"
+            );
+        }
+        family(
+            &mut out,
+            "cynthia_render_duration_seconds",
+            "histogram",
+            "Time spent rendering publications, by kind of publication.",
+        );
+        for (kind, histogram) in &m.renders {
+            histogram.write(&mut out, "cynthia_render_duration_seconds", "kind", kind);
+        }
+        family(
+            &mut out,
+            "cynthia_cache_hits_total",
+            "counter",
+            "Cache lookups that found a fresh object.",
+        );
+        let _ = writeln!(out, "cynthia_cache_hits_total {}", m.cache_hits);
+        family(
+            &mut out,
+            "cynthia_cache_misses_total",
+            "counter",
+            "Cache lookups that found nothing, or only a stale object.",
+        );
+        let _ = writeln!(out, "cynthia_cache_misses_total {}", m.cache_misses);
+        family(
+            &mut out,
+            "cynthia_cache_size_bytes",
+            "gauge",
+            "Estimated size of everything in the cache.",
+        );
+        let _ = writeln!(
+            out,
+            "cynthia_cache_size_bytes {}",
+            self.estimate_cache_size()
+        );
+        family(
+            &mut out,
+            "cynthia_cache_objects",
+            "gauge",
+            "Number of objects in the cache.",
+        );
+        let _ = writeln!(out, "cynthia_cache_objects {}", self.cache.len());
+        family(
+            &mut out,
+            "cynthia_eps_duration_seconds",
+            "histogram",
+            "Round trips to the external plugin server, by kind of request.",
+        );
+        for (request, histogram) in &m.eps {
+            histogram.write(&mut out, "cynthia_eps_duration_seconds", "request", request);
+        }
+        family(
+            &mut out,
+            "cynthia_plugin_errors_total",
+            "counter",
+            "Errors reported by plugins, by kind of request.",
+        );
+        for (request, count) in &m.plugin_errors {
+            let _ = writeln!(
+                out,
+                "cynthia_plugin_errors_total{{request=\"{request}\"}} {count}"
+            );
+        }
+        out
+    }
+}
+
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Names the route that answered a request, by the pattern it was matched with.
+pub(crate) fn route_kind(req: &HttpRequest) -> &'static str {
+    pattern_kind(req.method(), req.match_pattern().as_deref())
+}
+
+fn pattern_kind(method: &Method, pattern: Option<&str>) -> &'static str {
+    // Every route only takes GET and HEAD requests, anything else is dispatched to plugins.
+    if method != Method::GET && method != Method::HEAD {
+        return "dispatch";
+    }
+    let Some(pattern) = pattern else {
+        return "other";
+    };
+    match pattern {
+        "/{a:.*}" => "publication",
+        "/search.json" => "search",
+        "/robots.txt" => "robots",
+        p if p.starts_with("/assets/") => "asset",
+        p if p.starts_with("/api/") => "api",
+        p if p.starts_with("/search") => "search",
+        p if p.starts_with("/{file:sitemap") => "sitemap",
+        p if p.starts_with("/archive/") => "archive",
+        p if p.starts_with("/c/") || p.starts_with("/cat") => "category",
+        p if p.starts_with("/t/") || p.starts_with("/tag/") => "tag",
+        p if p.starts_with("/a/") || p.starts_with("/author/") => "author",
+        _ => "other",
+    }
+}
+
+/// Counts a response of the main server. Used as a middleware, so every route is counted the same
+/// way.
+pub(crate) async fn count<B>(response: &ServiceResponse<B>) {
+    let route = route_kind(response.request());
+    let status = response.status().as_u16();
+    if let Some(server_context_mutex) = response
+        .request()
+        .app_data::<Data<Arc<Mutex<ServerContext>>>>()
+    {
+        server_context_mutex
+            .lock_callback(|a| a.metrics.count_request(route, status))
+            .await;
+    }
+}
+
+async fn metrics(server_context_mutex: Data<Arc<Mutex<ServerContext>>>) -> HttpResponse {
+    let body = server_context_mutex
+        .lock_callback(|a| a.render_metrics())
+        .await;
+    HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, "text/plain; version=0.0.4"))
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .body(body)
+}
+
+/// Runs the metrics listener, if one is configured.
+pub(crate) async fn server(
+    config: Option<Metrics>,
+    server_context_mutex: Arc<Mutex<ServerContext>>,
+) {
+    let Some(config) = config else {
+        return;
+    };
+    let server_context_data = Data::new(server_context_mutex);
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(server_context_data.clone())
+            .route("/metrics", web::get().to(metrics))
+    })
+    .workers(1);
+    let listener = config.listener();
+    let bound = match listener.get_address() {
+        ListenAddress::Tcp(address) => server.bind(address),
+        #[cfg(unix)]
+        ListenAddress::Unix(path) => {
+            // A socket file left behind by an earlier run would make binding fail.
+            let _ = std::fs::remove_file(&path);
+            server.bind_uds(path)
+        }
+        #[cfg(not(unix))]
+        ListenAddress::Unix(_) => {
+            error!("Unix domain sockets are not supported on this platform.");
+            std::process::exit(1);
+        }
+    };
+    match bound {
+        Ok(server) => {
+            println!("Serving metrics on {}/metrics", listener.describe(false));
+            if let Err(e) = server.run().await {
+                error!("Metrics listener stopped: {e}");
+            }
+        }
+        Err(e) => {
+            error!("Could not bind to {}, error message: {}", config.address, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn histogram_exposition() {
+        let mut histogram = Histogram::default();
+        // Bounds are inclusive, and every bucket counts everything below it too.
+        for seconds in [0.0, 0.25, 1.0, 10.0] {
+            histogram.observe(Duration::from_secs_f64(seconds));
+        }
+        let mut out = String::new();
+        histogram.write(&mut out, "render_seconds", "kind", "page");
+        let expected = [
+            ("0.001", 1),
+            ("0.0025", 1),
+            ("0.005", 1),
+            ("0.01", 1),
+            ("0.025", 1),
+            ("0.05", 1),
+            ("0.1", 1),
+            ("0.25", 2),
+            ("0.5", 2),
+            ("1", 3),
+            ("2.5", 3),
+            ("5", 3),
+            ("+Inf", 4),
+        ]
+        .iter()
+        .map(|(le, count)| format!("render_seconds_bucket{{kind=\"page\",le=\"{le}\"}} {count}\n"))
+        .collect::<String>()
+            + "render_seconds_sum{kind=\"page\"} 11.25\n"
+            + "render_seconds_count{kind=\"page\"} 4\n";
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn empty_histogram() {
+        let mut out = String::new();
+        Histogram::default().write(&mut out, "eps_seconds", "request", "web");
+        assert_eq!(out.lines().count(), BUCKETS.len() + 3);
+        assert!(out.contains("eps_seconds_bucket{request=\"web\",le=\"+Inf\"} 0\n"));
+        assert!(out.ends_with(
+            "eps_seconds_sum{request=\"web\"} 0\neps_seconds_count{request=\"web\"} 0\n"
+        ));
+    }
+
+    #[test]
+    fn route_kinds() {
+        // The patterns of the routes in requestresponse.rs and api.rs.
+        for (pattern, kind) in [
+            ("/{a:.*}", "publication"),
+            ("/assets/{reqfile:.*}", "asset"),
+            ("/category/{c:.*}", "category"),
+            ("/c/{c:.*}", "category"),
+            ("/cat/{c:.*}", "category"),
+            ("/tag/{t:.*}", "tag"),
+            ("/t/{t:.*}", "tag"),
+            ("/author/{a:.*}", "author"),
+            ("/a/{a:.*}", "author"),
+            ("/archive/{d:.*}", "archive"),
+            ("/search", "search"),
+            ("/search/page/{n}", "search"),
+            ("/search.json", "search"),
+            ("/{file:sitemap(-[0-9]+)?\\.xml}", "sitemap"),
+            ("/robots.txt", "robots"),
+            ("/api/publications", "api"),
+            ("/api/publications/{id:.*}", "api"),
+            ("/api/tags", "api"),
+            ("/api/categories", "api"),
+        ] {
+            assert_eq!(pattern_kind(&Method::GET, Some(pattern)), kind, "{pattern}");
+            assert_eq!(
+                pattern_kind(&Method::HEAD, Some(pattern)),
+                kind,
+                "{pattern}"
+            );
+        }
+        assert_eq!(pattern_kind(&Method::GET, None), "other");
+        assert_eq!(pattern_kind(&Method::POST, Some("/{a:.*}")), "dispatch");
+        assert_eq!(pattern_kind(&Method::OPTIONS, None), "dispatch");
+    }
+
+    #[test]
+    fn unmatched_requests() {
+        let req = TestRequest::get().uri("/hello").to_http_request();
+        assert_eq!(route_kind(&req), "other");
+        let req = TestRequest::post().uri("/hello").to_http_request();
+        assert_eq!(route_kind(&req), "dispatch");
+    }
+}
@@ -4,7 +4,7 @@
  * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
  */
 
-use crate::config::{CynthiaConfClone, CynthiaConfig};
+use crate::config::{CynthiaConfClone, CynthiaConfig, Site};
 use crate::ServerContext;
 use actix_web::web::Data;
 use futures::Future;
@@ -18,21 +18,23 @@ use tokio::sync::Mutex;
 
 pub(crate) type CynthiaPublicationList = Vec<CynthiaPublication>;
 pub(crate) trait PostLists {
-    fn filter(&self, filter: PostListFilter) -> Vec<PostPublication>;
+    /// The posts a postlist with `filter` shows, in the order it shows them. Searches aren't
+    /// handled here, use [`crate::renders::list_posts`] for those.
+    fn filter(&self, filter: PostListFilter, site: &Site) -> Vec<PostPublication>;
     #[allow(dead_code)]
     fn get_by_id(&self, id: String) -> Option<CynthiaPublication>;
 }
 impl PostLists for CynthiaPostList {
-    fn filter(&self, filter: PostListFilter) -> Vec<PostPublication> {
+    fn filter(&self, filter: PostListFilter, site: &Site) -> Vec<PostPublication> {
         match filter {
             PostListFilter::Latest => {
                 let mut p = self.clone();
-                p.sort_by(|a, b| b.dates.published.cmp(&a.dates.published));
+                p.sort_by_key(|b| std::cmp::Reverse(b.dates.published));
                 p
             }
             PostListFilter::Oldest => {
                 let mut p = self.clone();
-                p.sort_by(|a, b| a.dates.published.cmp(&b.dates.published));
+                p.sort_by_key(|a| a.dates.published);
                 p
             }
             PostListFilter::Tag(tag) => self
@@ -56,19 +58,26 @@ impl PostLists for CynthiaPostList {
                 .filter(|x| {
                     x.author
                         .as_ref()
-                        .map_or(false, |a| a.name == Some(author.clone()))
-                })
-                .cloned()
-                .collect(),
-            PostListFilter::Search(search) => self
-                .iter()
-                .filter(|x| {
-                    x.title.contains(&search)
-                        || x.short.as_ref().map_or(false, |s| s.contains(&search))
-                    // || x.postcontent.get_inner().contains(&search)
+                        .and_then(|a| a.name.as_ref())
+                        .is_some_and(|name| slug(name) == slug(&author))
                 })
                 .cloned()
                 .collect(),
+            PostListFilter::Archive { year, month } => {
+                use chrono::Datelike;
+                let mut p: Vec<PostPublication> = self
+                    .iter()
+                    .filter(|x| {
+                        let date = site.local_date(x.dates.published);
+                        date.year() == year && month.is_none_or(|m| date.month() == m)
+                    })
+                    .cloned()
+                    .collect();
+                p.sort_by_key(|b| std::cmp::Reverse(b.dates.published));
+                p
+            }
+            // Searches need the search index, `renders::list_posts` sends them there.
+            PostListFilter::Search(_) => vec![],
         }
     }
     fn get_by_id(&self, id: String) -> Option<CynthiaPublication> {
@@ -96,6 +105,7 @@ impl PostLists for CynthiaPostList {
                     author: i.author.clone(),
                     postcontent: i.postcontent.clone(),
                     scene_override: i.scene_override.clone(),
+                    options: i.options.clone(),
                 })
             }
         }
@@ -108,6 +118,11 @@ pub(crate) trait CynthiaPublicationListTrait {
     fn get_notfound(&self, config: CynthiaConfClone) -> Option<CynthiaPublication>;
     fn get_root(&self) -> Option<CynthiaPublication>;
     fn get_by_id(&self, id: String) -> Option<CynthiaPublication>;
+    fn canonical_id(&self, id: &str, case_insensitive: bool) -> Option<String>;
+    fn tags(&self) -> Vec<String>;
+    fn categories(&self) -> Vec<String>;
+    fn authors(&self) -> Vec<String>;
+    fn archive(&self, site: &Site) -> Vec<ArchiveMonth>;
     fn validate(&self, config: CynthiaConfClone) -> bool;
     fn load(
         server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
@@ -128,6 +143,7 @@ impl CynthiaPublicationListTrait for CynthiaPublicationList {
                 author,
                 postcontent,
                 scene_override,
+                options,
             } = i
             {
                 p.push(PostPublication {
@@ -141,6 +157,7 @@ impl CynthiaPublicationListTrait for CynthiaPublicationList {
                     author: author.clone(),
                     postcontent: postcontent.clone(),
                     scene_override: scene_override.clone(),
+                    options: options.clone(),
                 });
             }
         }
@@ -178,9 +195,9 @@ impl CynthiaPublicationListTrait for CynthiaPublicationList {
         self.iter()
             .find(|x| match x.get_id().as_str() {
                 "root" | "" | "/" => match x {
-                    CynthiaPublication::Page { .. } => true,
+                    CynthiaPublication::Page { .. } | CynthiaPublication::PostList { .. } => true,
                     _ => {
-                        warn!("Page using a reserved id for root pages (\"/\", \"root\" or \"\") was not a page.");
+                        warn!("Publication using a reserved id for root pages (\"/\", \"root\" or \"\") was not a page or postlist.");
                         false
                 },
                 },
@@ -201,13 +218,88 @@ impl CynthiaPublicationListTrait for CynthiaPublicationList {
         };
         self.iter().find(|x| x.get_id() == id).cloned()
     }
+    /// Finds the id of the publication that `id` refers to, either by its own id or one of its
+    /// aliases. Exact matches win over matches that only differ in case.
+    fn canonical_id(&self, id: &str, case_insensitive: bool) -> Option<String> {
+        let names = |p: &CynthiaPublication| std::iter::once(p.get_id()).chain(p.get_aliases());
+        let exact = self
+            .iter()
+            .find(|p| names(p).any(|n| n.trim_matches('/') == id));
+        let found = match exact {
+            Some(p) => Some(p),
+            None if case_insensitive => self
+                .iter()
+                .find(|p| names(p).any(|n| n.trim_matches('/').eq_ignore_ascii_case(id))),
+            None => None,
+        };
+        found.map(|p| p.get_id())
+    }
+    /// Returns every tag used by a post, without duplicates.
+    fn tags(&self) -> Vec<String> {
+        let mut tags: Vec<String> = vec![];
+        for post in self.only_posts() {
+            for tag in post.tags {
+                if !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+        }
+        tags
+    }
+    /// Returns every category used by a post, without duplicates.
+    fn categories(&self) -> Vec<String> {
+        let mut categories: Vec<String> = vec![];
+        for post in self.only_posts() {
+            if let Some(category) = post.category {
+                if !categories.contains(&category) {
+                    categories.push(category);
+                }
+            }
+        }
+        categories
+    }
+    /// Returns the name of every post author, without duplicates. Names that share a slug count
+    /// as the same author.
+    fn authors(&self) -> Vec<String> {
+        let mut authors: Vec<String> = vec![];
+        for post in self.only_posts() {
+            if let Some(name) = post.author.and_then(|a| a.name) {
+                if !authors.iter().any(|a| slug(a) == slug(&name)) {
+                    authors.push(name);
+                }
+            }
+        }
+        authors
+    }
+    /// Counts the posts published in every month that has any, newest month first.
+    fn archive(&self, site: &Site) -> Vec<ArchiveMonth> {
+        use chrono::Datelike;
+        let mut months: Vec<ArchiveMonth> = vec![];
+        for post in self.only_posts() {
+            let date = site.local_date(post.dates.published);
+            match months
+                .iter_mut()
+                .find(|m| m.year == date.year() && m.month == date.month())
+            {
+                Some(m) => m.count += 1,
+                None => months.push(ArchiveMonth {
+                    year: date.year(),
+                    month: date.month(),
+                    count: 1,
+                    url: archive_path(date.year(), Some(date.month())),
+                }),
+            }
+        }
+        months.sort_by_key(|m| std::cmp::Reverse((m.year, m.month)));
+        months
+    }
     fn validate(&self, config: CynthiaConfClone) -> bool {
         // Collect validation results in a vector
         let mut valid: Vec<bool> = vec![];
 
         // Check for ids with reserved names or prefixes
         // - Reserved prefixes: "es/", "category/", "tag/", "virtual:"
-        let reserved_prefixes = vec!["es/", "category/", "tag/", "virtual:"];
+        let reserved_prefixes = ["es/", "category/", "tag/", "virtual:"];
         let reserved_prefix = self.iter().all(|x| {
             let id = x.get_id();
             if reserved_prefixes.iter().any(|&p| id.starts_with(p)) {
@@ -219,22 +311,63 @@ impl CynthiaPublicationListTrait for CynthiaPublicationList {
         });
         valid.push(reserved_prefix);
 
-        // Check for duplicate ids
-        let mut ids: Vec<String> = vec![];
-        let duplication = self.iter().all(|x| {
+        // Publications on the path of one of Cynthia's own routes are served instead of it
+        for x in self.iter() {
             let id = x.get_id();
-            if ids.contains(&id) {
-                error!("Duplicate id found in publication file: {}", id);
-                false
+            if let Some(route) = shadowed_route(id.trim_matches('/')) {
+                warn!(
+                    "Publication `{}` takes the place of the built-in {}.",
+                    id, route
+                );
+            }
+        }
+
+        // Tags and categories are a path segment of the URL of their list, and of the folder it's
+        // written to on export
+        let tags = self.tags().iter().all(|tag| {
+            if is_path_segment(tag) {
+                true
             } else {
-                ids.push(id);
+                error!(
+                    "Tag can't be used in a URL found in publication file: `{}`",
+                    tag
+                );
+                false
+            }
+        });
+        valid.push(tags);
+        let categories = self.categories().iter().all(|category| {
+            if is_path_segment(category) {
                 true
+            } else {
+                error!(
+                    "Category can't be used in a URL found in publication file: `{}`",
+                    category
+                );
+                false
             }
         });
+        valid.push(categories);
+
+        // Check for duplicate ids, aliases count as ids too
+        let mut ids: Vec<String> = vec![];
+        let duplication = self.iter().all(|x| {
+            std::iter::once(x.get_id())
+                .chain(x.get_aliases())
+                .all(|id| {
+                    if ids.contains(&id) {
+                        error!("Duplicate id found in publication file: {}", id);
+                        false
+                    } else {
+                        ids.push(id);
+                        true
+                    }
+                })
+        });
         valid.push(duplication);
         // Checking for required pages:
         // - 404 page
-        let notfound_exists = self.get_notfound(config).is_some();
+        let notfound_exists = self.get_notfound(config.clone()).is_some();
         if !notfound_exists {
             error!("404 page not found in publication file: Add a page with id being either \"404\" or \"notfound\" or the id specified in the config.");
         }
@@ -243,10 +376,79 @@ impl CynthiaPublicationListTrait for CynthiaPublicationList {
         // - Root page
         let root_exists = self.get_root().is_some();
         if !root_exists {
-            error!("Root page not found in publication file: Add a page with id being either \"root\" or \"/\"");
+            error!("Root page not found in publication file: Add a page or postlist with id being either \"root\" or \"/\"");
         }
         valid.push(root_exists);
 
+        // Redirects should use a redirecting status code, and should end up at an existing
+        // publication, without going in circles. Targets are looked up like requests are, so
+        // aliases and differently cased ids count.
+        let redirects = self.iter().all(|x| {
+            let CynthiaPublication::Redirect { id, status, .. } = x else {
+                return true;
+            };
+            if !REDIRECT_STATUSES.contains(status) {
+                error!(
+                    "Redirect `{}` uses status {}, use one of 301, 302, 307 or 308.",
+                    id, status
+                );
+                return false;
+            }
+            let mut visited = vec![id.clone()];
+            let mut current = x.clone();
+            while let CynthiaPublication::Redirect { target, .. } = &current {
+                if is_absolute_url(target) {
+                    break;
+                }
+                let target_id = match target.trim_matches('/') {
+                    "" => "root",
+                    t => t,
+                };
+                let target_id = match self.canonical_id(target_id, config.site.case_insensitive) {
+                    Some(id) => id,
+                    None if target_id == "root" && self.get_root().is_some() => break,
+                    None => {
+                        error!(
+                            "Redirect `{}` points to `{}`, which doesn't exist.",
+                            id, target_id
+                        );
+                        return false;
+                    }
+                };
+                if visited.contains(&target_id) {
+                    error!(
+                        "Redirect loop found in publication file: {} -> {}",
+                        visited.join(" -> "),
+                        target_id
+                    );
+                    return false;
+                }
+                let Some(next) = self.get_by_id(target_id.clone()) else {
+                    break;
+                };
+                current = next;
+                visited.push(target_id);
+            }
+            true
+        });
+        valid.push(redirects);
+
+        // Sitemap priorities run from 0.0 to 1.0
+        let priorities = self
+            .iter()
+            .all(|x| match x.get_options().and_then(|o| o.priority) {
+                Some(priority) if !(0.0..=1.0).contains(&priority) => {
+                    error!(
+                        "Publication `{}` has sitemap priority {}, use a number from 0.0 to 1.0.",
+                        x.get_id(),
+                        priority
+                    );
+                    false
+                }
+                _ => true,
+            });
+        valid.push(priorities);
+
         // An empty list is not valid
         let itemsin = if self.is_empty() {
             error!("No correct publications found in publication list.");
@@ -335,16 +537,18 @@ impl CynthiaPublicationListTrait for CynthiaPublicationList {
 }
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub(crate) struct PostPublication {
-    id: String,
-    title: String,
-    short: Option<String>,
-    dates: CynthiaPublicationDates,
-    thumbnail: Option<String>,
-    category: Option<String>,
-    tags: Vec<String>,
-    author: Option<Author>,
-    postcontent: PublicationContent,
-    scene_override: Option<String>,
+    pub(crate) id: String,
+    pub(crate) title: String,
+    pub(crate) short: Option<String>,
+    pub(crate) dates: CynthiaPublicationDates,
+    pub(crate) thumbnail: Option<String>,
+    pub(crate) category: Option<String>,
+    pub(crate) tags: Vec<String>,
+    pub(crate) author: Option<Author>,
+    pub(crate) postcontent: PublicationContent,
+    pub(crate) scene_override: Option<String>,
+    #[serde(flatten)]
+    pub(crate) options: PublicationOptions,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -361,6 +565,8 @@ pub(crate) enum CynthiaPublication {
         #[serde(alias = "scene")]
         #[serde(alias = "scene-override")]
         scene_override: Option<String>,
+        #[serde(flatten)]
+        options: PublicationOptions,
     },
     #[serde(alias = "post")]
     Post {
@@ -378,6 +584,8 @@ pub(crate) enum CynthiaPublication {
         #[serde(alias = "scene")]
         #[serde(alias = "scene-override")]
         scene_override: Option<String>,
+        #[serde(flatten)]
+        options: PublicationOptions,
     },
     #[serde(alias = "postlist")]
     #[serde(alias = "selection")]
@@ -388,17 +596,230 @@ pub(crate) enum CynthiaPublication {
         #[serde(alias = "description")]
         short: Option<String>,
         filter: PostListFilter,
+        /// How many posts to show per page, `site.per_page` is used when not set.
+        #[serde(alias = "per-page")]
+        #[serde(alias = "perPage")]
+        #[serde(default)]
+        per_page: Option<usize>,
         #[serde(alias = "scene")]
         #[serde(alias = "scene-override")]
         scene_override: Option<String>,
+        #[serde(flatten)]
+        options: PublicationOptions,
+    },
+    #[serde(alias = "redirect")]
+    Redirect {
+        id: String,
+        /// Either the id of another publication, or an absolute URL.
+        #[serde(alias = "to")]
+        target: String,
+        /// One of 301, 302, 307 or 308.
+        #[serde(alias = "code")]
+        #[serde(default = "c_redirect_status")]
+        status: u16,
+        /// Other ids this publication can be found under, redirecting to its own id.
+        #[serde(default)]
+        aliases: Vec<String>,
     },
 }
+/// Options every page, post and postlist has, on top of its own fields. The `Default` is what
+/// virtual publications get: no overrides, and left out of the sitemap.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct PublicationOptions {
+    /// Overrides the `Cache-Control` header for this publication.
+    #[serde(alias = "cache-control")]
+    #[serde(alias = "cacheControl")]
+    #[serde(default)]
+    pub(crate) cache_control: Option<String>,
+    /// Other ids this publication can be found under, redirecting to its own id.
+    #[serde(default)]
+    pub(crate) aliases: Vec<String>,
+    /// Whether this publication is listed in `/sitemap.xml`.
+    #[serde(default = "c_in_sitemap")]
+    pub(crate) sitemap: bool,
+    /// The sitemap priority of this publication, from 0.0 to 1.0.
+    #[serde(alias = "sitemap-priority")]
+    #[serde(alias = "sitemapPriority")]
+    #[serde(default)]
+    pub(crate) priority: Option<f32>,
+    /// Robots directives for this publication, like `"noindex, nofollow"`, instead of the
+    /// site's default.
+    #[serde(default)]
+    pub(crate) robots: Option<String>,
+    /// Shorthand for `"robots": "noindex"`.
+    #[serde(default)]
+    pub(crate) noindex: bool,
+}
+impl PublicationOptions {
+    /// The robots directives these options set, if any.
+    pub(crate) fn robots(&self) -> Option<String> {
+        match &self.robots {
+            Some(r) => Some(r.clone()),
+            None if self.noindex => Some(String::from("noindex")),
+            None => None,
+        }
+    }
+}
+fn c_redirect_status() -> u16 {
+    301
+}
+fn c_in_sitemap() -> bool {
+    true
+}
+/// The status codes a redirect publication may use.
+const REDIRECT_STATUSES: [u16; 4] = [301, 302, 307, 308];
 impl CynthiaPublication {
     pub fn get_id(&self) -> String {
         match self {
             CynthiaPublication::Page { id, .. } => id.to_string(),
             CynthiaPublication::Post { id, .. } => id.to_string(),
             CynthiaPublication::PostList { id, .. } => id.to_string(),
+            CynthiaPublication::Redirect { id, .. } => id.to_string(),
+        }
+    }
+
+    /// The virtual postlist served on `/tag/{tag}`.
+    pub(crate) fn virtual_tag_list(tag: &str) -> Self {
+        CynthiaPublication::PostList {
+            id: format!("tag:{}", tag),
+            title: format!("Tag: {}", tag),
+            short: None,
+            filter: PostListFilter::Tag(tag.to_string()),
+            per_page: None,
+            scene_override: None,
+            options: PublicationOptions::default(),
+        }
+    }
+
+    /// The virtual postlist served on `/category/{category}`.
+    pub(crate) fn virtual_category_list(category: &str) -> Self {
+        CynthiaPublication::PostList {
+            id: format!("category:{}", category),
+            title: format!("Category: {}", category),
+            short: None,
+            filter: PostListFilter::Category(category.to_string()),
+            per_page: None,
+            scene_override: None,
+            options: PublicationOptions::default(),
+        }
+    }
+
+    /// The virtual postlist served on `/author/{slug}`.
+    pub(crate) fn virtual_author_list(name: &str) -> Self {
+        CynthiaPublication::PostList {
+            id: format!("author:{}", slug(name)),
+            title: format!("Author: {}", name),
+            short: None,
+            filter: PostListFilter::Author(name.to_string()),
+            per_page: None,
+            scene_override: None,
+            options: PublicationOptions::default(),
+        }
+    }
+
+    /// The virtual postlist served on `/archive/{year}` or `/archive/{year}/{month}`.
+    pub(crate) fn virtual_archive_list(year: i32, month: Option<u32>) -> Self {
+        let (id, title) = match month.and_then(|m| chrono::NaiveDate::from_ymd_opt(year, m, 1)) {
+            Some(date) => (
+                format!("archive:{}", date.format("%Y-%m")),
+                format!("Archive: {}", date.format("%B %Y")),
+            ),
+            None => (format!("archive:{}", year), format!("Archive: {}", year)),
+        };
+        CynthiaPublication::PostList {
+            id,
+            title,
+            short: None,
+            filter: PostListFilter::Archive { year, month },
+            per_page: None,
+            scene_override: None,
+            options: PublicationOptions::default(),
+        }
+    }
+
+    /// The virtual postlist of search results served on `/search?q={query}`.
+    pub(crate) fn virtual_search_list(query: &str) -> Self {
+        CynthiaPublication::PostList {
+            id: String::from("search"),
+            title: format!("Search: {}", query),
+            short: None,
+            filter: PostListFilter::Search(query.to_string()),
+            per_page: None,
+            scene_override: None,
+            options: PublicationOptions {
+                // Search results are endless, crawlers can follow them to posts but shouldn't
+                // index them.
+                robots: Some(String::from("noindex, follow")),
+                ..PublicationOptions::default()
+            },
+        }
+    }
+
+    /// Returns a `virtual:` id, which `get_by_id` resolves back into this publication without it
+    /// being listed in the publication file.
+    pub(crate) fn virtual_id(&self) -> String {
+        format!("virtual:{}", serde_json::to_string(self).unwrap())
+    }
+
+    /// The path this publication is served on. Virtual tag, category, author, archive and search
+    /// lists have their own routes.
+    pub(crate) fn get_url_path(&self) -> String {
+        match self {
+            CynthiaPublication::PostList {
+                id,
+                filter: PostListFilter::Tag(tag),
+                ..
+            } if id.starts_with("tag:") => format!("/tag/{}", tag),
+            CynthiaPublication::PostList {
+                id,
+                filter: PostListFilter::Category(category),
+                ..
+            } if id.starts_with("category:") => format!("/category/{}", category),
+            CynthiaPublication::PostList {
+                id,
+                filter: PostListFilter::Author(name),
+                ..
+            } if id.starts_with("author:") => format!("/author/{}", slug(name)),
+            CynthiaPublication::PostList {
+                id,
+                filter: PostListFilter::Archive { year, month },
+                ..
+            } if id.starts_with("archive:") => archive_path(*year, *month),
+            CynthiaPublication::PostList {
+                id,
+                filter: PostListFilter::Search(query),
+                ..
+            } if id == "search" => format!("/search?q={}", urlencoding::encode(query)),
+            _ => url_path(&self.get_id()),
+        }
+    }
+
+    /// The options of pages, posts and postlists. Redirects don't have these.
+    pub(crate) fn get_options(&self) -> Option<&PublicationOptions> {
+        match self {
+            CynthiaPublication::Page { options, .. } => Some(options),
+            CynthiaPublication::Post { options, .. } => Some(options),
+            CynthiaPublication::PostList { options, .. } => Some(options),
+            CynthiaPublication::Redirect { .. } => None,
+        }
+    }
+
+    pub(crate) fn get_aliases(&self) -> Vec<String> {
+        match self {
+            CynthiaPublication::Redirect { aliases, .. } => aliases.clone(),
+            _ => self
+                .get_options()
+                .map(|o| o.aliases.clone())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Publication dates, postlists and redirects don't have these.
+    pub(crate) fn get_dates(&self) -> Option<CynthiaPublicationDates> {
+        match self {
+            CynthiaPublication::Page { dates, .. } => Some(dates.clone()),
+            CynthiaPublication::Post { dates, .. } => Some(dates.clone()),
+            CynthiaPublication::PostList { .. } | CynthiaPublication::Redirect { .. } => None,
         }
     }
 
@@ -407,8 +828,106 @@ impl CynthiaPublication {
             CynthiaPublication::Page { scene_override, .. } => scene_override.clone(),
             CynthiaPublication::Post { scene_override, .. } => scene_override.clone(),
             CynthiaPublication::PostList { scene_override, .. } => scene_override.clone(),
+            CynthiaPublication::Redirect { .. } => None,
         }
     }
+
+    /// Where a redirect publication points to, as a `Location` header value, together with its
+    /// status code. `None` for any other publication.
+    pub(crate) fn get_redirect(&self) -> Option<(String, u16)> {
+        match self {
+            CynthiaPublication::Redirect { target, status, .. } => {
+                let location = if is_absolute_url(target) {
+                    target.clone()
+                } else {
+                    url_path(target)
+                };
+                Some((location, *status))
+            }
+            _ => None,
+        }
+    }
+}
+/// The robots directives of a publication: its own, `noindex` for the 404 page, or else the
+/// site's default.
+pub(crate) fn robots_directives(
+    publication: &CynthiaPublication,
+    published: &CynthiaPublicationList,
+    config: &CynthiaConfClone,
+) -> String {
+    if let Some(robots) = publication.get_options().and_then(|o| o.robots()) {
+        return robots;
+    }
+    let is_notfound = published
+        .get_notfound(config.clone())
+        .is_some_and(|p| p.get_id() == publication.get_id());
+    if is_notfound {
+        return String::from("noindex");
+    }
+    config.site.robots.directives.clone()
+}
+/// Whether robots directives like `"noindex, follow"` keep a publication out of search engines.
+pub(crate) fn is_noindex(directives: &str) -> bool {
+    directives.split(',').any(|d| {
+        let d = d.trim();
+        d.eq_ignore_ascii_case("noindex") || d.eq_ignore_ascii_case("none")
+    })
+}
+/// The path a publication with this id is served on.
+pub(crate) fn url_path(id: &str) -> String {
+    match id.trim_matches('/') {
+        "" | "root" => String::from("/"),
+        id => format!("/{}", id),
+    }
+}
+/// The path of the archive of a year, or of a month in it.
+pub(crate) fn archive_path(year: i32, month: Option<u32>) -> String {
+    match month {
+        Some(month) => format!("/archive/{}/{:02}", year, month),
+        None => format!("/archive/{}", year),
+    }
+}
+/// The built-in route, like the search page, the sitemap or `robots.txt`, that a publication with
+/// `id` takes the place of, if any.
+fn shadowed_route(id: &str) -> Option<&'static str> {
+    let sitemap_part = id
+        .strip_prefix("sitemap-")
+        .and_then(|n| n.strip_suffix(".xml"))
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+    match id {
+        "search" | "search.json" => Some("search page"),
+        "sitemap.xml" => Some("sitemap"),
+        "robots.txt" => Some("robots.txt"),
+        _ if id.starts_with("search/page/") => Some("search page"),
+        _ if id.starts_with("author/") || id.starts_with("a/") => Some("author list"),
+        _ if id.starts_with("archive/") => Some("archive"),
+        _ if id.starts_with("api/") => Some("JSON API"),
+        _ if sitemap_part => Some("sitemap"),
+        _ => None,
+    }
+}
+/// Whether `name` can be a single segment of a path: not empty, without slashes, and not `.` or
+/// `..`.
+fn is_path_segment(name: &str) -> bool {
+    !name.is_empty() && !name.contains(['/', '\\']) && name != "." && name != ".."
+}
+/// Turns an author name into the form used in URLs: lowercase, with every run of characters that
+/// aren't letters or digits replaced by a single dash. "Mar (@strawmelonjuice)" becomes
+/// `mar-strawmelonjuice`.
+pub(crate) fn slug(name: &str) -> String {
+    let mut slug = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+/// Whether a redirect target is a URL rather than a publication id.
+fn is_absolute_url(target: &str) -> bool {
+    target.contains("://") || target.starts_with("//")
 }
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub(crate) struct CynthiaPublicationDates {
@@ -430,6 +949,22 @@ pub(crate) enum PostListFilter {
     Author(String),
     #[serde(alias = "search")]
     Search(String),
+    /// Posts published in a year, or in a month of it, newest first.
+    #[serde(alias = "archive")]
+    Archive {
+        year: i32,
+        #[serde(default)]
+        month: Option<u32>,
+    },
+}
+/// The number of posts published in a month, for archive listings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ArchiveMonth {
+    pub(crate) year: i32,
+    pub(crate) month: u32,
+    pub(crate) count: usize,
+    /// Where the archive of this month is served.
+    pub(crate) url: String,
 }
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub(crate) enum PublicationContent {
@@ -469,3 +1004,79 @@ pub(crate) struct Author {
     pub(crate) thumbnail: Option<String>,
     pub(crate) link: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugs() {
+        assert_eq!(slug("Mar (@strawmelonjuice)"), "mar-strawmelonjuice");
+        assert_eq!(slug("mar-strawmelonjuice"), "mar-strawmelonjuice");
+        assert_eq!(slug("  Jane   Doe  "), "jane-doe");
+        assert_eq!(slug("--a--b--"), "a-b");
+        assert_eq!(slug("Ünïcødé Nämé"), "ünïcødé-nämé");
+        assert_eq!(slug("R2-D2"), "r2-d2");
+        assert_eq!(slug("../etc"), "etc");
+        assert_eq!(slug("@!?"), "");
+        assert_eq!(slug(""), "");
+    }
+
+    #[test]
+    fn slugs_are_stable() {
+        // Author lists redirect to the slug of a name, so slugging it again can't change it.
+        for name in [
+            "Mar (@strawmelonjuice)",
+            "Jane Doe",
+            "Ünïcødé Nämé",
+            "a_b.c",
+        ] {
+            assert_eq!(slug(&slug(name)), slug(name));
+        }
+    }
+
+    fn page(id: &str, aliases: &[&str]) -> serde_json::Value {
+        serde_json::json!({
+            "page": {
+                "id": id,
+                "title": id,
+                "dates": { "altered": 0, "published": 0 },
+                "content": { "Inline": { "as": "Html", "value": "" } },
+                "aliases": aliases,
+            }
+        })
+    }
+
+    fn redirect(id: &str, target: &str) -> serde_json::Value {
+        serde_json::json!({ "redirect": { "id": id, "target": target } })
+    }
+
+    fn valid(publications: Vec<serde_json::Value>) -> bool {
+        let published: CynthiaPublicationList =
+            serde_json::from_value(serde_json::Value::Array(publications)).unwrap();
+        published.validate(crate::config::CynthiaConf::default().clone())
+    }
+
+    #[test]
+    fn redirect_targets() {
+        let pages = || {
+            vec![
+                page("root", &[]),
+                page("404", &[]),
+                page("Hello", &["old/hello"]),
+            ]
+        };
+        let with = |extra: Vec<serde_json::Value>| [pages(), extra].concat();
+        assert!(valid(with(vec![redirect("a", "Hello")])));
+        // Requests follow aliases and, with `case_insensitive` on, ignore case.
+        assert!(valid(with(vec![redirect("a", "old/hello")])));
+        assert!(valid(with(vec![redirect("a", "/hello/")])));
+        assert!(valid(with(vec![redirect("a", "b"), redirect("b", "/")])));
+        assert!(!valid(with(vec![redirect("a", "nope")])));
+        // A redirect to an alias of itself, or another redirect back, goes in circles.
+        assert!(!valid(with(vec![redirect("a", "b"), redirect("b", "a")])));
+        assert!(!valid(with(vec![serde_json::json!({
+            "redirect": { "id": "a", "target": "old/a", "aliases": ["old/a"] }
+        })])));
+    }
+}
@@ -6,19 +6,28 @@
 use actix_web::web::Data;
 use log::error;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
 use crate::config::CynthiaConfClone;
-use crate::publications::{CynthiaPostList, CynthiaPublicationList, CynthiaPublicationListTrait};
+use crate::publications::{
+    CynthiaPostList, CynthiaPublication, CynthiaPublicationList, CynthiaPublicationListTrait,
+};
 use crate::{LockCallback, ServerContext};
 
 pub(crate) enum PGIDCheckResponse {
     Ok,
     Error,
     NotFound,
+    /// The publication is a redirect, with its `Location` and status code.
+    Redirect(String, u16),
 }
 
+/// Query parameters of a request, as handed to templates. When a parameter is repeated, the last
+/// value is used.
+pub(crate) type QueryParams = BTreeMap<String, String>;
+
 #[derive(Clone)]
 pub(crate) enum RenderrerResponse {
     Error,
@@ -73,6 +82,9 @@ pub(crate) async fn check_pgid(
         return PGIDCheckResponse::Error;
     }
     let publication = published.get_by_id(page_id);
+    if let Some((location, status)) = publication.as_ref().and_then(|p| p.get_redirect()) {
+        return PGIDCheckResponse::Redirect(location, status);
+    }
     if publication.is_none() {
         let publication = published.get_by_id(server_context.config.site.notfound_page.clone());
         if publication.is_none() {
@@ -87,8 +99,12 @@ pub(crate) async fn check_pgid(
         PGIDCheckResponse::Ok
     }
 }
+/// Renders publication `pgid`. `page` is the page number of a postlist, starting at 1. Pages that
+/// don't exist are [`RenderrerResponse::NotFound`].
 pub(crate) async fn render_from_pgid(
     pgid: String,
+    page: usize,
+    query: QueryParams,
     server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
 ) -> RenderrerResponse {
     let config = server_context_mutex
@@ -107,12 +123,63 @@ pub(crate) async fn render_from_pgid(
             RenderrerResponse::NotFound
         }
     } else if let Some(pb) = publication {
-        in_renderer::render_controller(pb, server_context_mutex.clone()).await
+        let kind = match pb {
+            CynthiaPublication::Page { .. } => "page",
+            CynthiaPublication::Post { .. } => "post",
+            CynthiaPublication::PostList { .. } => "postlist",
+            CynthiaPublication::Redirect { .. } => "redirect",
+        };
+        let start = std::time::Instant::now();
+        let response =
+            in_renderer::render_controller(pb, page, query, server_context_mutex.clone()).await;
+        server_context_mutex
+            .lock_callback(|a| a.metrics.observe_render(kind, start.elapsed()))
+            .await;
+        response
     } else {
         RenderrerResponse::Error
     }
 }
 
+/// Renders the content of a post or page to HTML, without putting it in a template. Returns `None`
+/// if the content can't be fetched or rendered.
+pub(crate) async fn render_content(
+    content: crate::publications::PublicationContent,
+) -> Option<String> {
+    match in_renderer::fetch_page_ish_content(content)
+        .await
+        .unwrap_html()
+    {
+        RenderrerResponse::Ok(html) => Some(html),
+        _ => None,
+    }
+}
+
+/// The posts a postlist with `filter` shows, in the order it shows them.
+pub(crate) async fn list_posts(
+    filter: crate::publications::PostListFilter,
+    publicationlist: &CynthiaPublicationList,
+    server_context_mutex: &Data<Arc<Mutex<ServerContext>>>,
+) -> crate::publications::CynthiaPostList {
+    use crate::publications::{PostListFilter, PostLists};
+    match filter {
+        // Searches go through the index, which ranks the results.
+        PostListFilter::Search(q) => {
+            crate::search::search(server_context_mutex, publicationlist, &q)
+                .await
+                .into_iter()
+                .map(|(post, _)| post)
+                .collect()
+        }
+        filter => {
+            let site = server_context_mutex
+                .lock_callback(|a| a.config.site.clone())
+                .await;
+            publicationlist.only_posts().filter(filter, &site)
+        }
+    }
+}
+
 /// This struct is a stripped down version of the Scene struct in the config module.
 /// It stores only the necessary data for rendering a single publication.
 struct PublicationScene {
@@ -125,11 +192,32 @@ struct PublicationScene {
 pub(crate) struct PageLikePublicationTemplateData {
     meta: PageLikePublicationTemplateDataMeta,
     content: String,
+    query: QueryParams,
+    /// Post counts per month, for archive listings.
+    archive: Vec<crate::publications::ArchiveMonth>,
 }
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub(crate) struct PostListPublicationTemplateData {
     meta: PageLikePublicationTemplateDataMeta,
     posts: CynthiaPostList,
+    query: QueryParams,
+    pagination: Pagination,
+    /// Post counts per month, for archive listings.
+    archive: Vec<crate::publications::ArchiveMonth>,
+    /// Where the feeds of this list are, for `<link rel="alternate">`.
+    feeds: crate::feeds::FeedLinks,
+}
+/// Where a page of a postlist lies among the others.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Pagination {
+    /// The current page, starting at 1.
+    current: usize,
+    total: usize,
+    per_page: usize,
+    /// The total number of posts in the list, over all pages.
+    posts: usize,
+    previous: Option<String>,
+    next: Option<String>,
 }
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct PageLikePublicationTemplateDataMeta {
@@ -146,7 +234,7 @@ struct PageLikePublicationTemplateDataMeta {
 mod in_renderer {
     use super::*;
     use crate::externalpluginservers::EPSRequestBody;
-    use crate::publications::{CynthiaPostList, CynthiaPublicationListTrait, PostLists};
+    use crate::publications::{CynthiaPostList, CynthiaPublicationListTrait};
     use crate::tell::CynthiaColors;
     use crate::{
         config::{CynthiaConfig, Scene, SceneCollectionTrait},
@@ -160,11 +248,17 @@ mod in_renderer {
 
     pub(super) async fn render_controller(
         publication: CynthiaPublication,
+        page: usize,
+        query: QueryParams,
         server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
     ) -> RenderrerResponse {
         let config = server_context_mutex
             .lock_callback(|a| a.config.clone())
             .await;
+        // Only postlists have more than one page.
+        if page == 0 || (page > 1 && !matches!(publication, CynthiaPublication::PostList { .. })) {
+            return RenderrerResponse::NotFound;
+        }
         let scene = fetch_scene(publication.clone(), config.clone());
 
         if scene.is_none() {
@@ -172,6 +266,13 @@ mod in_renderer {
             return RenderrerResponse::Error;
         };
         let scene = scene.unwrap();
+        let url_path = publication.get_url_path();
+        let publicationlist: CynthiaPublicationList =
+            CynthiaPublicationList::load(server_context_mutex.clone()).await;
+        let archive = publicationlist.archive(&config.site);
+        let feeds = crate::feeds::FeedLinks::for_publication(&publication, &config);
+        let robots =
+            crate::publications::robots_directives(&publication, &publicationlist, &config);
         let localscene = match publication {
             CynthiaPublication::Page { .. } => PublicationScene {
                 template: scene.templates.page.clone(),
@@ -191,6 +292,10 @@ mod in_renderer {
                 script: scene.script.clone(),
                 kind: "postlist".to_string(),
             },
+            CynthiaPublication::Redirect { id, .. } => {
+                error!("Publication `{}` is a redirect, it can't be rendered.", id);
+                return RenderrerResponse::Error;
+            }
         };
 
         let mut pageish_template_data: PageLikePublicationTemplateData =
@@ -222,6 +327,8 @@ mod in_renderer {
                         RenderrerResponse::Ok(s) => s,
                         _ => return RenderrerResponse::Error,
                     },
+                    query: query.clone(),
+                    archive: archive.clone(),
                 }
             }
             CynthiaPublication::Post {
@@ -251,6 +358,8 @@ mod in_renderer {
                         RenderrerResponse::Ok(s) => s,
                         _ => return RenderrerResponse::Error,
                     },
+                    query: query.clone(),
+                    archive: archive.clone(),
                 }
             }
             CynthiaPublication::PostList {
@@ -258,12 +367,20 @@ mod in_renderer {
                 title,
                 short,
                 filter,
+                per_page,
                 ..
             } => {
-                let publicationlist: CynthiaPublicationList =
-                    CynthiaPublicationList::load(server_context_mutex.clone()).await;
-                let postlist: CynthiaPostList = publicationlist.only_posts();
-                let filtered_postlist = postlist.filter(filter);
+                let filtered_postlist =
+                    list_posts(filter, &publicationlist, &server_context_mutex).await;
+                let (filtered_postlist, pagination) = match paginate(
+                    filtered_postlist,
+                    page,
+                    per_page.unwrap_or(config.site.per_page),
+                    &url_path,
+                ) {
+                    Some(p) => p,
+                    None => return RenderrerResponse::NotFound,
+                };
                 postlist_template_data = PostListPublicationTemplateData {
                     meta: PageLikePublicationTemplateDataMeta {
                         id: id.clone(),
@@ -279,10 +396,16 @@ mod in_renderer {
                         thumbnail: None,
                     },
                     posts: filtered_postlist,
+                    query: query.clone(),
+                    pagination,
+                    archive: archive.clone(),
+                    feeds: feeds.clone(),
                 };
                 pageish_template_data.meta = postlist_template_data.meta.clone();
                 // println!("{}", serde_json::to_string(&postlist_template_data).unwrap());
             }
+            // Redirects already returned when picking a scene.
+            CynthiaPublication::Redirect { .. } => unreachable!(),
         };
 
         let outerhtml: String = {
@@ -299,8 +422,15 @@ mod in_renderer {
                 return RenderrerResponse::Error;
             }
 
+            // Postlist templates get the postlist data, also when rendered by the fallback.
+            let builtin_data = if localscene.kind == *"postlist" {
+                serde_json::to_value(&postlist_template_data)
+            } else {
+                serde_json::to_value(&pageish_template_data)
+            }
+            .unwrap_or_default();
             // A fallback function that uses the builtin handlebars renderer.
-            let builtin_handlebars = |data| {
+            let builtin_handlebars = |data: serde_json::Value| {
                 let mut template = Handlebars::new();
                 // streq helper
                 // This helper checks if two strings are equal.
@@ -332,8 +462,7 @@ mod in_renderer {
             };
             let mut htmlbody: String = if !cfg!(feature = "js_runtime") {
                 // Fall back to builtin handlebars if the js_runtime feature is not enabled.
-                if let RenderrerResponse::Ok(a) = builtin_handlebars(pageish_template_data.clone())
-                {
+                if let RenderrerResponse::Ok(a) = builtin_handlebars(builtin_data.clone()) {
                     a
                 } else {
                     return RenderrerResponse::Error;
@@ -362,8 +491,7 @@ mod in_renderer {
             } else {
                 warn!("External Javascript Runtime failed to render the content. Retrying with basic builtin rendering.");
                 // Fall back to builtin handlebars if the external plugin server fails.
-                if let RenderrerResponse::Ok(a) = builtin_handlebars(pageish_template_data.clone())
-                {
+                if let RenderrerResponse::Ok(a) = builtin_handlebars(builtin_data.clone()) {
                     a
                 } else {
                     return RenderrerResponse::Error;
@@ -386,7 +514,13 @@ mod in_renderer {
             );
             head.push_str("\n\t\t<meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\" />");
             head.push_str("\n\t\t<meta name=\"generator\" content=\"strawmelonjuice-Cynthia\" />");
-            head.push_str("\n\t\t<meta name=\"robots\" content=\"index, follow\" />");
+            head.push_str(
+                format!(
+                    "\n\t\t<meta name=\"robots\" content=\"{}\" />",
+                    robots.replace('"', "&quot;")
+                )
+                .as_str(),
+            );
             if let Some(stylefile) = localscene.stylesheet {
                 let path: PathBuf = std::env::current_dir()
                     .unwrap()
@@ -459,6 +593,49 @@ mod in_renderer {
         // content.unwrap().unwrap_html();
         RenderrerResponse::Ok(outerhtml)
     }
+    /// Cuts page `page` out of a list of posts. Returns `None` if the list has no such page, though
+    /// the first page always exists, even when empty. `per_page` 0 puts all posts on one page.
+    fn paginate(
+        posts: CynthiaPostList,
+        page: usize,
+        per_page: usize,
+        url_path: &str,
+    ) -> Option<(CynthiaPostList, Pagination)> {
+        let count = posts.len();
+        let per_page = if per_page == 0 {
+            count.max(1)
+        } else {
+            per_page
+        };
+        let total = count.div_ceil(per_page).max(1);
+        if page > total {
+            return None;
+        }
+        // Lists that carry a query in their path, like search results, keep it on every page.
+        let (path, list_query) = match url_path.split_once('?') {
+            Some((path, q)) => (path, format!("?{}", q)),
+            None => (url_path, String::new()),
+        };
+        let page_url = |n: usize| match n {
+            1 => format!("{}{}", path, list_query),
+            n => format!("{}/page/{}{}", path.trim_end_matches('/'), n, list_query),
+        };
+        let pagination = Pagination {
+            current: page,
+            total,
+            per_page,
+            posts: count,
+            previous: (page > 1).then(|| page_url(page - 1)),
+            next: (page < total).then(|| page_url(page + 1)),
+        };
+        let posts = posts
+            .into_iter()
+            .skip((page - 1) * per_page)
+            .take(per_page)
+            .collect();
+        Some((posts, pagination))
+    }
+
     fn fetch_scene(publication: CynthiaPublication, config: CynthiaConfClone) -> Option<Scene> {
         let scene = publication.get_scene_name();
         match scene {
@@ -479,13 +656,13 @@ mod in_renderer {
     }
 
     #[derive(Debug)]
-    enum FetchedContent {
+    pub(super) enum FetchedContent {
         Error,
         Ok(ContentType),
     }
 
     impl FetchedContent {
-        fn unwrap_html(self) -> RenderrerResponse {
+        pub(super) fn unwrap_html(self) -> RenderrerResponse {
             match self {
                 FetchedContent::Ok(c) => match c {
                     Html(h) => RenderrerResponse::Ok(h),
@@ -506,7 +683,7 @@ mod in_renderer {
         target_type: ContentType,
     }
     #[doc = "Fetches the content of a pageish (a post or a page) publication."]
-    async fn fetch_page_ish_content(content: PublicationContent) -> FetchedContent {
+    pub(super) async fn fetch_page_ish_content(content: PublicationContent) -> FetchedContent {
         let content_output = match content {
             PublicationContent::Inline(c) => ContentSource {
                 inner: c.get_inner(),
@@ -583,6 +760,59 @@ mod in_renderer {
 
         FetchedContent::Ok(contenttype)
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn posts(count: u64) -> CynthiaPostList {
+            (0..count)
+                .map(|n| {
+                    serde_json::from_value(serde_json::json!({
+                        "id": n.to_string(),
+                        "title": n.to_string(),
+                        "dates": { "altered": n, "published": n },
+                        "tags": [],
+                        "postcontent": { "Inline": { "as": "Html", "value": "" } },
+                    }))
+                    .unwrap()
+                })
+                .collect()
+        }
+
+        #[test]
+        fn page_urls() {
+            // These are the paths `split_page` in requestresponse.rs parses back.
+            let (page, pagination) = paginate(posts(25), 2, 10, "/posts").unwrap();
+            assert_eq!(page.len(), 10);
+            assert_eq!(page[0].id, "10");
+            assert_eq!(pagination.total, 3);
+            assert_eq!(pagination.previous.as_deref(), Some("/posts"));
+            assert_eq!(pagination.next.as_deref(), Some("/posts/page/3"));
+
+            let (_, pagination) = paginate(posts(25), 2, 10, "/tag/rust/").unwrap();
+            assert_eq!(pagination.previous.as_deref(), Some("/tag/rust/"));
+            assert_eq!(pagination.next.as_deref(), Some("/tag/rust/page/3"));
+
+            let (_, pagination) = paginate(posts(25), 2, 10, "/search?q=rust").unwrap();
+            assert_eq!(pagination.previous.as_deref(), Some("/search?q=rust"));
+            assert_eq!(pagination.next.as_deref(), Some("/search/page/3?q=rust"));
+        }
+
+        #[test]
+        fn page_bounds() {
+            let (page, pagination) = paginate(posts(25), 3, 10, "/posts").unwrap();
+            assert_eq!(page.len(), 5);
+            assert_eq!(pagination.next, None);
+            assert!(paginate(posts(25), 4, 10, "/posts").is_none());
+            // The first page always exists, and `per_page` 0 puts every post on it.
+            let (page, pagination) = paginate(posts(0), 1, 10, "/posts").unwrap();
+            assert!(page.is_empty());
+            assert_eq!((pagination.total, pagination.previous), (1, None));
+            let (page, pagination) = paginate(posts(25), 1, 0, "/posts").unwrap();
+            assert_eq!((page.len(), pagination.total), (25, 1));
+        }
+    }
 }
 #[cfg(feature = "js_runtime")]
 mod inlines {
@@ -811,6 +1041,7 @@ mod inlines {
     }
 }
 
+#[allow(dead_code)]
 pub(crate) mod json_html {
 
     #[cfg(test)]
@@ -924,6 +1155,7 @@ pub(crate) mod json_html {
     trait ToHtmlFallible {
         fn to_html(&self) -> Result<String, String>;
     }
+    #[allow(clippy::wrong_self_convention)]
     trait FromString {
         fn from_string(self) -> Result<ContentBlocksJson, String>;
     }
@@ -1099,9 +1331,9 @@ pub(crate) mod json_html {
                         }
                     }
                 }
-                ContentBlock::DivBlock { inner } => todo!(),
-                ContentBlock::SpanBlock { inner } => todo!(),
-                ContentBlock::Button { inner } => todo!(),
+                ContentBlock::DivBlock { .. } => todo!(),
+                ContentBlock::SpanBlock { .. } => todo!(),
+                ContentBlock::Button { .. } => todo!(),
             }
         }
     }
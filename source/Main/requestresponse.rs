@@ -1,23 +1,53 @@
-use crate::publications::CynthiaPublication;
+use crate::publications::{self, CynthiaPublication};
 /*
  * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
  *
  * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
  */
 use crate::tell::CynthiaColors;
-use actix_web::web::Data;
-use actix_web::{get, post, HttpRequest, HttpResponse, Responder};
-use log::{debug, trace, warn};
+use actix_web::http::header;
+use actix_web::http::{Method, StatusCode};
+use actix_web::web::{self, Data};
+use actix_web::{route, HttpRequest, HttpResponse, Responder};
+use base64::Engine;
+use log::{debug, error, trace, warn};
 use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+use crate::accesslog::{self, Source};
 use crate::cache::CynthiaCacheExtraction;
-use crate::config::CynthiaConfig;
+use crate::config::{CynthiaConfClone, CynthiaConfig, Site};
 use crate::externalpluginservers::{contact_eps, EPSRequestBody};
-use crate::renders::render_from_pgid;
+use crate::feeds::{self, FeedKind};
+use crate::publications::{
+    robots_directives, url_path, CynthiaPublicationList, CynthiaPublicationListTrait,
+    PostListFilter,
+};
+use crate::renders::{render_from_pgid, QueryParams};
 use crate::LockCallback;
-use crate::{renders, ServerContext};
+use crate::{renders, search, sitemap, ServerContext};
+use compression::Encoding;
+use conditional::Validators;
+use incoming::IncomingBody;
+use ranges::{RangeRequest, RangeSource};
+
+pub(crate) mod api;
+mod caching;
+mod compression;
+mod conditional;
+mod incoming;
+mod normalise;
+mod ranges;
+
+/// The requested path and query. HTTP/2 requests carry the full URL in their URI, where HTTP/1.1
+/// requests only carry the path, so this is used instead of `req.uri()` to treat them the same.
+fn request_uri(req: &HttpRequest) -> String {
+    req.uri()
+        .path_and_query()
+        .map(|pq| pq.to_string())
+        .unwrap_or_default()
+}
 
 fn urlspace() -> (usize, usize) {
     let fullwidth = termsize::get().unwrap().cols as usize;
@@ -41,13 +71,20 @@ fn urlspace() -> (usize, usize) {
     // (53, 55)
 }
 
-#[get("/{a:.*}")]
+#[route("/{a:.*}", method = "GET", method = "HEAD")]
 #[doc = r"Serves pages included in CynthiaConfig, or a default page if not found."]
 pub(crate) async fn serve(
     server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
     req: HttpRequest,
 ) -> impl Responder {
-    let (w_s, w_a) = urlspace();
+    serve_request(server_context_mutex, req).await
+}
+
+/// Answers a request the way [`serve`] does: plugins get the first go, then publications.
+pub(crate) async fn serve_request(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    req: HttpRequest,
+) -> HttpResponse {
     // We can't lock the mutex here because it wouldn't be usable by EPS, so we need to use a callback.
     // let mut server_context: MutexGuard<ServerContext> = server_context_mutex.lock().await;
     let config_clone = server_context_mutex
@@ -56,13 +93,68 @@ pub(crate) async fn serve(
             a.config.clone()
         })
         .await;
+    if let Some(response) = plugin_answer(&server_context_mutex, &req, &config_clone).await {
+        return response;
+    }
+    let published = CynthiaPublicationList::load(server_context_mutex.clone()).await;
+    publication_response(server_context_mutex, req, config_clone, published).await
+}
+
+/// Lets plugins, and publications on the same path, answer requests for Cynthia's own routes
+/// first, as they could before those routes existed. Returns `None` if neither does, so the route
+/// can answer itself.
+pub(crate) async fn claimed(
+    server_context_mutex: &Data<Arc<Mutex<ServerContext>>>,
+    req: &HttpRequest,
+) -> Option<HttpResponse> {
+    let config_clone = server_context_mutex
+        .lock_callback(|a| a.config.clone())
+        .await;
+    let response = match plugin_answer(server_context_mutex, req, &config_clone).await {
+        Some(response) => response,
+        None => {
+            let published = CynthiaPublicationList::load(server_context_mutex.clone()).await;
+            if !owns_path(&published, req.path(), &config_clone.site) {
+                return None;
+            }
+            publication_response(
+                server_context_mutex.clone(),
+                req.clone(),
+                config_clone,
+                published,
+            )
+            .await
+        }
+    };
+    server_context_mutex
+        .lock_callback(|a| a.request_count += 1)
+        .await;
+    Some(response)
+}
+
+/// Whether a publication is served on `path`, or on the postlist it's a page or feed of.
+fn owns_path(published: &CynthiaPublicationList, path: &str, site: &Site) -> bool {
+    let path = path.trim_matches('/');
+    let is_postlist = |base: &str| postlist_of(published, base).is_some();
+    published
+        .canonical_id(path, site.case_insensitive)
+        .is_some()
+        || matches!(feeds::split_feed(path), (base, Some(_)) if is_postlist(base))
+        || matches!(split_page(path), (base, Some(_)) if is_postlist(base))
+}
 
-    let page_uri = if req.uri() == "" {
+/// Asks plugins to answer a request. Returns `None` if none of them does.
+async fn plugin_answer(
+    server_context_mutex: &Data<Arc<Mutex<ServerContext>>>,
+    req: &HttpRequest,
+    config_clone: &CynthiaConfClone,
+) -> Option<HttpResponse> {
+    let (w_s, w_a) = urlspace();
+    let page_uri = if request_uri(req).is_empty() {
         "root".to_string()
     } else {
-        req.uri().to_string()
+        request_uri(req)
     };
-    let page_id = page_uri.trim_start_matches('/');
     let headers = {
         // Transform it into makeshift JSON!
         let json_kinda = format!("{:?}", &req.headers().iter().collect::<Vec<_>>())
@@ -77,7 +169,12 @@ pub(crate) async fn serve(
         EPSRequestBody::WebRequest {
             uri: page_uri.clone(),
             headers,
-            method: "get".to_string(),
+            method: req.method().as_str().to_lowercase(),
+            query: incoming::query(req),
+            body: None,
+            body_base64: false,
+            form: vec![],
+            files: vec![],
         },
     )
     .await;
@@ -85,14 +182,24 @@ pub(crate) async fn serve(
         crate::externalpluginservers::EPSResponseBody::WebResponse {
             append_headers,
             response_body,
+            status,
+            body_base64,
+            body_file,
         } => {
+            let response = plugin_response(
+                append_headers,
+                response_body,
+                status,
+                body_base64,
+                body_file,
+            );
             let coninfo = req.connection_info();
             let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
             config_clone.tell(format!(
                 "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
-                "GET:200".color_ok_green(),
+                status_label(req, &response),
                 {
-                    let uri = req.uri().to_string();
+                    let uri = request_uri(req);
                     if uri == *"" {
                         "/".to_string()
                     } else {
@@ -102,22 +209,72 @@ pub(crate) async fn serve(
                 ip.color_lightblue(),
                 "extern".color_pink()
             ));
-            let mut response = HttpResponse::build(actix_web::http::StatusCode::OK);
-            for (k, v) in append_headers {
-                response.append_header((k, v));
-            }
-            return response.body(response_body);
+            accesslog::mark(req, Source::Plugin);
+            Some(response)
         }
         crate::externalpluginservers::EPSResponseBody::NoneOk
-        | crate::externalpluginservers::EPSResponseBody::Disabled => (),
-        _ => return HttpResponse::InternalServerError().body("Internal server error."),
+        | crate::externalpluginservers::EPSResponseBody::Disabled => None,
+        _ => Some(HttpResponse::InternalServerError().body("Internal server error.")),
+    }
+}
+
+/// Serves the publication on the path of `req`, or the 404 page if there is none.
+async fn publication_response(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    req: HttpRequest,
+    config_clone: CynthiaConfClone,
+    published: CynthiaPublicationList,
+) -> HttpResponse {
+    let (w_s, w_a) = urlspace();
+    // Neither the query string nor trailing slashes are part of the id, unwanted slashes are
+    // redirected away below.
+    let page_id = req.path().trim_matches('/');
+    let query: QueryParams = incoming::query(&req).into_iter().collect();
+    if let Some(location) = normalise::canonical_location(&req, &published, &config_clone.site) {
+        return moved_permanently(&req, &config_clone, location);
+    }
+    // `{postlist}/feed.xml` and `{postlist}/atom.xml` are its feeds, unless a publication has that
+    // exact id.
+    if let (base, Some(kind)) = feeds::split_feed(page_id) {
+        if published.get_by_id(page_id.to_string()).is_none() {
+            if let Some(publication) = postlist_of(&published, base) {
+                return feed(
+                    &server_context_mutex,
+                    &req,
+                    &config_clone,
+                    publication,
+                    kind,
+                )
+                .await;
+            }
+        }
+    }
+    // `{postlist}/page/{n}` is page n of a postlist, unless a publication has that exact id.
+    let (page_id, page_number) = match split_page(page_id) {
+        (base, Some(n))
+            if published.get_by_id(page_id.to_string()).is_none()
+                && postlist_of(&published, base).is_some() =>
+        {
+            (base, Some(n))
+        }
+        _ => (page_id, None),
     };
+    if page_number == Some(1) {
+        return moved_permanently(&req, &config_clone, with_query(&req, url_path(page_id)));
+    }
+    let page_number = page_number.unwrap_or(1);
+    let cache_id = render_cache_id(
+        page_id,
+        page_number,
+        &query,
+        config_clone.cache.include_query,
+    );
     let s = renders::check_pgid(page_id.to_string(), server_context_mutex.clone()).await;
     match s {
         renders::PGIDCheckResponse::Ok => {
             let from_cache: bool;
             let cache_result = server_context_mutex
-                .lock_callback(|servercontext| servercontext.get_cache(page_id, 0))
+                .lock_callback(|servercontext| servercontext.get_cache(&cache_id, 0))
                 .await;
             let page = match cache_result {
                 Some(c) => {
@@ -127,30 +284,73 @@ pub(crate) async fn serve(
                 None => {
                     from_cache = false;
                     // Now that we're past the EPS, we can lock the mutex for this scope.
-                    let page =
-                        render_from_pgid(page_id.parse().unwrap(), server_context_mutex.clone())
-                            .await;
+                    let page = render_from_pgid(
+                        page_id.to_string(),
+                        page_number,
+                        query.clone(),
+                        server_context_mutex.clone(),
+                    )
+                    .await;
+                    if page.is_not_found() {
+                        return not_found(&server_context_mutex, &req, &config_clone, query).await;
+                    }
                     let mut server_context = server_context_mutex.lock().await;
                     server_context
                         .store_cache(
-                            page_id,
+                            &cache_id,
                             page.clone().unwrap().as_bytes(),
                             config_clone.clone().cache.lifetimes.served,
                         )
                         .unwrap();
-                    server_context
-                        .get_cache(page_id, config_clone.clone().cache.lifetimes.served)
-                        .unwrap_or(CynthiaCacheExtraction(page.unwrap().as_bytes().to_vec(), 0))
+                    // Reading it back would count as a cache hit.
+                    CynthiaCacheExtraction(page.unwrap().into_bytes(), 0, None)
                 }
             };
+            if from_cache {
+                accesslog::mark(&req, Source::Cache);
+            }
+
+            let publication = if page_id.is_empty() {
+                published.get_root()
+            } else {
+                published.get_by_id(page_id.to_string())
+            };
+            let robots = publication
+                .as_ref()
+                .map(|p| robots_directives(p, &published, &config_clone));
+            let cache_control = publication
+                .and_then(|p| p.get_options().and_then(|o| o.cache_control.clone()))
+                .unwrap_or_else(|| caching::for_lifetime(config_clone.cache.lifetimes.served));
+            let compressed = match Encoding::negotiate(&req) {
+                Some(encoding) => compression::compressed_variant(
+                    &server_context_mutex,
+                    &cache_id,
+                    encoding,
+                    &page.0,
+                    None,
+                    true,
+                )
+                .await
+                .map(|body| (encoding, body)),
+                None => None,
+            };
+            // No `Last-Modified`: templates, the config and plugins change a page as much as its
+            // own dates do, so only the ETag can tell whether it changed.
+            let validators = Validators::new(&page.0, None)
+                .for_encoding(compressed.as_ref().map(|(encoding, _)| *encoding));
+            let not_modified = validators.not_modified(&req);
 
             let coninfo = req.connection_info();
             let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
             config_clone.tell(format!(
                 "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
-                "GET:200".color_ok_green(),
+                if not_modified {
+                    format!("{}:304", req.method()).color_ok_green()
+                } else {
+                    format!("{}:200", req.method()).color_ok_green()
+                },
                 {
-                    let uri = req.uri().to_string();
+                    let uri = request_uri(&req);
                     if uri == *"" {
                         "/".to_string()
                     } else {
@@ -166,21 +366,38 @@ pub(crate) async fn serve(
                     }
                 }
             ));
-            HttpResponse::Ok()
-                .append_header(("Content-Type", "text/html; charset=utf-8"))
-                .body(page.0)
-        }
-        renders::PGIDCheckResponse::Error => {
-            HttpResponse::InternalServerError().body("Internal server error.")
+            if not_modified {
+                return validators.not_modified_response(&cache_control);
+            }
+            let mut response = HttpResponse::Ok();
+            response.append_header(("Content-Type", "text/html; charset=utf-8"));
+            response.insert_header((header::VARY, "Accept-Encoding"));
+            response.insert_header((header::CACHE_CONTROL, cache_control));
+            if let Some(robots) = robots {
+                response.insert_header(("X-Robots-Tag", robots));
+            }
+            validators.apply(&mut response);
+            match compressed {
+                Some((encoding, body)) => {
+                    response.insert_header((header::CONTENT_ENCODING, encoding.name()));
+                    response.body(body)
+                }
+                None => response.body(page.0),
+            }
         }
-        renders::PGIDCheckResponse::NotFound => {
-            let coninfo = req.connection_info().clone();
+        renders::PGIDCheckResponse::Redirect(location, status) => {
+            let response = HttpResponse::build(
+                StatusCode::from_u16(status).unwrap_or(StatusCode::MOVED_PERMANENTLY),
+            )
+            .insert_header((header::LOCATION, location))
+            .finish();
+            let coninfo = req.connection_info();
             let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
-            warn!(
+            config_clone.tell(format!(
                 "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
-                "GET:404".color_error_red(),
+                status_label(&req, &response),
                 {
-                    let uri = req.uri().to_string();
+                    let uri = request_uri(&req);
                     if uri == *"" {
                         "/".to_string()
                     } else {
@@ -188,107 +405,221 @@ pub(crate) async fn serve(
                     }
                 },
                 ip.color_lightblue(),
-                "not found".color_red()
-            );
+                "redirect".color_lilac()
+            ));
+            response
+        }
+        renders::PGIDCheckResponse::Error => {
+            HttpResponse::InternalServerError().body("Internal server error.")
+        }
+        renders::PGIDCheckResponse::NotFound => {
+            not_found(&server_context_mutex, &req, &config_clone, query).await
+        }
+    }
+}
 
-            HttpResponse::NotFound()
-                .append_header(("Content-Type", "text/html; charset=utf-8"))
-                .body(
-                    render_from_pgid(
-                        config_clone.site.notfound_page.clone(),
-                        server_context_mutex.clone(),
-                    )
+/// Logs a 404 and responds with the site's not-found page.
+async fn not_found(
+    server_context_mutex: &Data<Arc<Mutex<ServerContext>>>,
+    req: &HttpRequest,
+    config_clone: &CynthiaConfClone,
+    query: QueryParams,
+) -> HttpResponse {
+    let (w_s, w_a) = urlspace();
+    let coninfo = req.connection_info().clone();
+    let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+    warn!(
+        "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+        format!("{}:404", req.method()).color_error_red(),
+        {
+            let uri = request_uri(req);
+            if uri == *"" {
+                "/".to_string()
+            } else {
+                uri
+            }
+        },
+        ip.color_lightblue(),
+        "not found".color_red()
+    );
+
+    let published = CynthiaPublicationList::load(server_context_mutex.clone()).await;
+    let robots = published
+        .get_notfound(config_clone.clone())
+        .map(|p| robots_directives(&p, &published, config_clone))
+        .unwrap_or_else(|| String::from("noindex"));
+    HttpResponse::NotFound()
+        .append_header(("Content-Type", "text/html; charset=utf-8"))
+        .insert_header(("X-Robots-Tag", robots))
+        .body(
+            render_from_pgid(
+                config_clone.site.notfound_page.clone(),
+                1,
+                query,
+                server_context_mutex.clone(),
+            )
+            .await
+            .unwrap(),
+        )
+}
+
+/// Serves the RSS or Atom feed of a postlist.
+async fn feed(
+    server_context_mutex: &Data<Arc<Mutex<ServerContext>>>,
+    req: &HttpRequest,
+    config_clone: &CynthiaConfClone,
+    publication: CynthiaPublication,
+    kind: FeedKind,
+) -> HttpResponse {
+    let (w_s, w_a) = urlspace();
+    let cache_id = feeds::feed_path(&publication.get_url_path(), kind);
+    let cache_result = server_context_mutex
+        .lock_callback(|servercontext| servercontext.get_cache(&cache_id, 0))
+        .await;
+    let (body, from_cache) = match cache_result {
+        Some(c) => (c.0, true),
+        None => match feeds::render_feed(server_context_mutex, &publication, kind).await {
+            Some(xml) => {
+                server_context_mutex
+                    .lock()
                     .await
-                    .unwrap(),
-                )
+                    .store_cache(
+                        &cache_id,
+                        xml.as_bytes(),
+                        config_clone.cache.lifetimes.served,
+                    )
+                    .unwrap();
+                (xml.into_bytes(), false)
+            }
+            None => {
+                let query: QueryParams = incoming::query(req).into_iter().collect();
+                return not_found(server_context_mutex, req, config_clone, query).await;
+            }
+        },
+    };
+    if from_cache {
+        accesslog::mark(req, Source::Cache);
+    }
+    let cache_control = caching::for_lifetime(config_clone.cache.lifetimes.served);
+    let validators = Validators::new(&body, None);
+    let not_modified = validators.not_modified(req);
+    let coninfo = req.connection_info();
+    let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+    config_clone.tell(format!(
+        "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+        if not_modified {
+            format!("{}:304", req.method()).color_ok_green()
+        } else {
+            format!("{}:200", req.method()).color_ok_green()
+        },
+        request_uri(req),
+        ip.color_lightblue(),
+        if from_cache {
+            "cache".color_green()
+        } else {
+            "feed".color_yellow()
+        }
+    ));
+    if not_modified {
+        return validators.not_modified_response(&cache_control);
+    }
+    let mut response = HttpResponse::Ok();
+    response.insert_header((header::CONTENT_TYPE, kind.content_type()));
+    response.insert_header((header::CACHE_CONTROL, cache_control));
+    validators.apply(&mut response);
+    response.body(body)
+}
+
+/// The postlist a feed or page path starts with. Paths like `/feed.xml` start with nothing, they
+/// belong to the root.
+fn postlist_of(published: &CynthiaPublicationList, base: &str) -> Option<CynthiaPublication> {
+    let publication = if base.is_empty() {
+        published.get_root()
+    } else {
+        published.get_by_id(base.to_string())
+    };
+    publication.filter(|p| matches!(p, CynthiaPublication::PostList { .. }))
+}
+
+/// Permanently redirects to the canonical `location` of a request.
+fn moved_permanently(
+    req: &HttpRequest,
+    config_clone: &CynthiaConfClone,
+    location: String,
+) -> HttpResponse {
+    let (w_s, w_a) = urlspace();
+    let response = HttpResponse::MovedPermanently()
+        .insert_header((header::LOCATION, location))
+        .finish();
+    let coninfo = req.connection_info();
+    let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+    config_clone.tell(format!(
+        "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+        status_label(req, &response),
+        request_uri(req),
+        ip.color_lightblue(),
+        "normalised".color_lilac()
+    ));
+    response
+}
+
+/// Appends the query string of a request to `path`.
+fn with_query(req: &HttpRequest, path: String) -> String {
+    match req.query_string() {
+        "" => path,
+        query => format!("{path}?{query}"),
+    }
+}
+
+/// Splits `{id}/page/{n}` into the id and the page number. A bare `page/{n}` is a page of the
+/// root, with an empty id.
+fn split_page(path: &str) -> (&str, Option<usize>) {
+    let trimmed = path.trim_end_matches('/');
+    let split = trimmed
+        .rsplit_once("/page/")
+        .or_else(|| trimmed.strip_prefix("page/").map(|n| ("", n)));
+    match split {
+        Some((base, n))
+            if !n.is_empty()
+                && n.bytes().all(|b| b.is_ascii_digit())
+                && (n == "0" || !n.starts_with('0')) =>
+        {
+            (base, n.parse().ok())
         }
+        _ => (path, None),
     }
 }
 
-#[get("/assets/{reqfile:.*}")]
+#[route("/assets/{reqfile:.*}", method = "GET", method = "HEAD")]
 pub(crate) async fn assets_with_cache(
     server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
     req: HttpRequest,
 ) -> impl Responder {
     let (w_s, w_a) = urlspace();
     let path = req.match_info().get("reqfile").unwrap();
-    let cacheresulr = server_context_mutex
-        .lock_callback(|servercontext| servercontext.get_cache(path, 0))
+    let filepath: PathBuf = std::env::current_dir()
+        .unwrap()
+        .canonicalize()
+        .unwrap()
+        .join("cynthiaFiles/assets/")
+        .join(path);
+    let config_clone = server_context_mutex
+        .lock_callback(|a| {
+            a.request_count += 1;
+            a.config.clone()
+        })
         .await;
-    match cacheresulr {
-        None => {
-            let config_clone = server_context_mutex
-                .lock_callback(|a| {
-                    a.request_count += 1;
-                    a.config.clone()
-                })
-                .await;
-            let filepath: PathBuf = std::env::current_dir()
-                .unwrap()
-                .canonicalize()
-                .unwrap()
-                .join("cynthiaFiles/assets/")
-                .join(path);
-            debug!("Requested asset: {:?}", filepath);
-            if filepath.exists() && filepath.is_file() {
-                let contents: Vec<u8> = std::fs::read(filepath).unwrap();
-                let mut server_context = server_context_mutex.lock().await;
-                server_context
-                    .store_cache(path, &contents, config_clone.cache.lifetimes.assets)
-                    .unwrap();
-                let coninfo = req.connection_info();
-                let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
-                server_context.tell(format!(
-                    "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
-                    "GET:200".color_ok_green(),
-                    {
-                        let uri = req.uri().to_string();
-                        if uri == *"" {
-                            "/".to_string()
-                        } else {
-                            uri
-                        }
-                    },
-                    ip.color_lightblue(),
-                    "filesystem".color_lilac()
-                ));
-                HttpResponse::Ok()
-                    .append_header(("Content-Type", "text/html; charset=utf-8"))
-                    .body(contents)
-            } else {
-                let coninfo = req.connection_info();
-                let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
-                config_clone.tell(format!(
-                    "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
-                    "GET:404".color_error_red(),
-                    {
-                        let uri = req.uri().to_string();
-                        if uri == *"" {
-                            "/".to_string()
-                        } else {
-                            uri
-                        }
-                    },
-                    ip.color_lightblue(),
-                    "not found".color_red()
-                ));
-                HttpResponse::NotFound().body("404 Not Found")
-            }
-        }
-        Some(c) => {
-            let config_clone = server_context_mutex
-                .lock_callback(|a| {
-                    a.request_count += 1;
-                    a.config.clone()
-                })
-                .await;
+    debug!("Requested asset: {:?}", filepath);
+    let metadata = match std::fs::metadata(&filepath) {
+        Ok(m) if m.is_file() => m,
+        _ => {
             let coninfo = req.connection_info();
             let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
             config_clone.tell(format!(
                 "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
-                "GET:200".color_ok_green(),
+                format!("{}:404", req.method()).color_error_red(),
                 {
-                    let uri = req.uri().to_string();
+                    let uri = request_uri(&req);
                     if uri == *"" {
                         "/".to_string()
                     } else {
@@ -296,22 +627,286 @@ pub(crate) async fn assets_with_cache(
                     }
                 },
                 ip.color_lightblue(),
-                "cache".color_green()
+                "not found".color_red()
             ));
-            HttpResponse::Ok()
-                .append_header(("Content-Type", "text/html; charset=utf-8"))
-                .body(c.0)
+            return HttpResponse::NotFound().body("404 Not Found");
+        }
+    };
+    // Large files (like audio and video) are streamed from disk instead of going through the cache.
+    let streamed = metadata.len() > config_clone.cache.stream_threshold;
+    // A precompressed sibling of a streamed file is streamed instead, if the client takes it. Not
+    // for range requests though, ranges always apply to the uncompressed file.
+    let precompressed = match Encoding::negotiate(&req) {
+        Some(encoding) if streamed && !req.headers().contains_key(header::RANGE) => {
+            let sibling = encoding.sibling(&filepath);
+            std::fs::metadata(&sibling)
+                .ok()
+                .filter(|m| m.is_file())
+                .map(|m| (encoding, sibling, m))
+        }
+        _ => None,
+    };
+    let (source, length, content_type, validators, origin) = if streamed {
+        accesslog::mark(&req, Source::File);
+        let mut head = Vec::new();
+        if let Ok(file) = std::fs::File::open(&filepath) {
+            let _ = std::io::Read::read_to_end(&mut std::io::Read::take(file, 8192), &mut head);
+        }
+        let content_type = crate::files::content_type(&filepath, &head);
+        match &precompressed {
+            Some((encoding, sibling, sibling_metadata)) => (
+                RangeSource::File(sibling),
+                sibling_metadata.len(),
+                content_type,
+                Validators::from_metadata(sibling_metadata.len(), sibling_metadata.modified().ok())
+                    .for_encoding(Some(*encoding)),
+                "stream".color_lilac(),
+            ),
+            None => (
+                RangeSource::File(&filepath),
+                metadata.len(),
+                content_type,
+                Validators::from_metadata(metadata.len(), metadata.modified().ok()),
+                "stream".color_lilac(),
+            ),
+        }
+    } else {
+        let cacheresult = server_context_mutex
+            .lock_callback(|servercontext| servercontext.get_cache(path, 0))
+            .await;
+        match cacheresult {
+            Some(c) => {
+                accesslog::mark(&req, Source::Cache);
+                let content_type =
+                    c.2.unwrap_or_else(|| crate::files::content_type(&filepath, &c.0));
+                let validators = Validators::new(&c.0, metadata.modified().ok());
+                (
+                    RangeSource::Memory(c.0),
+                    metadata.len(),
+                    content_type,
+                    validators,
+                    "cache".color_green(),
+                )
+            }
+            None => {
+                let contents: Vec<u8> = match std::fs::read(&filepath) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        error!("Could not read asset {}: {}", filepath.display(), e);
+                        return HttpResponse::InternalServerError().body("Internal server error.");
+                    }
+                };
+                accesslog::mark(&req, Source::File);
+                let content_type = crate::files::content_type(&filepath, &contents);
+                server_context_mutex
+                    .lock()
+                    .await
+                    .store_cache_with_type(
+                        path,
+                        &contents,
+                        Some(&content_type),
+                        config_clone.cache.lifetimes.assets,
+                    )
+                    .unwrap();
+                let validators = Validators::new(&contents, metadata.modified().ok());
+                (
+                    RangeSource::Memory(contents),
+                    metadata.len(),
+                    content_type,
+                    validators,
+                    "filesystem".color_lilac(),
+                )
+            }
+        }
+    };
+    // Compression is skipped for range requests, ranges always apply to the uncompressed file.
+    let compressed = match (&source, Encoding::negotiate(&req)) {
+        (RangeSource::Memory(bytes), Some(encoding))
+            if !req.headers().contains_key(header::RANGE) =>
+        {
+            compression::compressed_variant(
+                &server_context_mutex,
+                path,
+                encoding,
+                bytes,
+                Some(encoding.sibling(&filepath)),
+                compression::compressible(&content_type),
+            )
+            .await
+            .map(|body| (encoding, body))
+        }
+        _ => None,
+    };
+    // Streamed files only differ by encoding if they have precompressed siblings.
+    let vary = !streamed
+        || [Encoding::Brotli, Encoding::Gzip]
+            .iter()
+            .any(|e| e.sibling(&filepath).is_file());
+    let (source, validators, encoding) = match compressed {
+        Some((encoding, body)) => (
+            RangeSource::Memory(body),
+            validators.for_encoding(Some(encoding)),
+            Some(encoding),
+        ),
+        None => (
+            source,
+            validators,
+            precompressed.as_ref().map(|(encoding, ..)| *encoding),
+        ),
+    };
+    let length = match &source {
+        RangeSource::Memory(bytes) => bytes.len() as u64,
+        RangeSource::File(_) => length,
+    };
+    let not_modified = validators.not_modified(&req);
+    let range_request = if not_modified {
+        RangeRequest::Full
+    } else {
+        RangeRequest::from_request(&req, length, &validators)
+    };
+
+    let coninfo = req.connection_info();
+    let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+    config_clone.tell(format!(
+        "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+        match range_request {
+            _ if not_modified => format!("{}:304", req.method()).color_ok_green(),
+            RangeRequest::Full => format!("{}:200", req.method()).color_ok_green(),
+            RangeRequest::Partial(_) => format!("{}:206", req.method()).color_ok_green(),
+            RangeRequest::Unsatisfiable => format!("{}:416", req.method()).color_error_red(),
+        },
+        {
+            let uri = request_uri(&req);
+            if uri == *"" {
+                "/".to_string()
+            } else {
+                uri
+            }
+        },
+        ip.color_lightblue(),
+        origin
+    ));
+    let cache_control = caching::for_asset(&config_clone.cache.lifetimes, &filepath, &content_type);
+    if not_modified {
+        return validators.not_modified_response(&cache_control);
+    }
+    let mut response = HttpResponse::Ok();
+    validators.apply(&mut response);
+    response.insert_header((header::CACHE_CONTROL, cache_control));
+    if vary {
+        response.insert_header((header::VARY, "Accept-Encoding"));
+    }
+    if let Some(encoding) = encoding {
+        response.insert_header((header::CONTENT_ENCODING, encoding.name()));
+    }
+    match ranges::respond(response, range_request, source, length, &content_type) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Could not serve asset {}: {}", filepath.display(), e);
+            HttpResponse::InternalServerError().body("Internal server error.")
+        }
+    }
+}
+
+/// Turns a plugin's `WebResponse` into an actual response. Plugins can set a status code, send
+/// binary data base64 encoded, or point to a file that is streamed from disk.
+fn plugin_response(
+    append_headers: Vec<(String, String)>,
+    response_body: String,
+    status: Option<u16>,
+    body_base64: bool,
+    body_file: Option<String>,
+) -> HttpResponse {
+    let status = match StatusCode::from_u16(status.unwrap_or(200)) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("A plugin responded with an invalid status code: {}", e);
+            return HttpResponse::InternalServerError().body("Internal server error.");
         }
+    };
+    let mut response = HttpResponse::build(status);
+    for (k, v) in append_headers {
+        response.append_header((k, v));
     }
+    if let Some(path) = body_file {
+        let path = PathBuf::from(path);
+        let length = match std::fs::metadata(&path) {
+            Ok(m) if m.is_file() => m.len(),
+            _ => {
+                error!(
+                    "A plugin responded with a file that doesn't exist: {}",
+                    path.display()
+                );
+                return HttpResponse::InternalServerError().body("Internal server error.");
+            }
+        };
+        return match ranges::file_stream(&path, 0, length) {
+            Ok(stream) => response.no_chunking(length).streaming(stream),
+            Err(e) => {
+                error!("Could not stream {}: {}", path.display(), e);
+                HttpResponse::InternalServerError().body("Internal server error.")
+            }
+        };
+    }
+    if body_base64 {
+        return match base64::engine::general_purpose::STANDARD.decode(response_body) {
+            Ok(body) => response.body(body),
+            Err(e) => {
+                error!("A plugin responded with an invalid base64 body: {}", e);
+                HttpResponse::InternalServerError().body("Internal server error.")
+            }
+        };
+    }
+    response.body(response_body)
+}
+
+/// The `METHOD:status` label used when logging a response.
+fn status_label(req: &HttpRequest, response: &HttpResponse) -> String {
+    let label = format!("{}:{}", req.method(), response.status().as_u16());
+    if response.status().is_client_error() || response.status().is_server_error() {
+        label.color_error_red()
+    } else {
+        label.color_ok_green()
+    }
+}
+
+/// The cache id of a rendered page. The query string is only part of it when `cache.include_query`
+/// is set, with its parameters sorted so their order doesn't matter.
+fn render_cache_id(page_id: &str, page: usize, query: &QueryParams, include_query: bool) -> String {
+    let page_id = match page {
+        1 => page_id.to_string(),
+        n => format!("{}/page/{}", page_id, n),
+    };
+    if !include_query || query.is_empty() {
+        return page_id;
+    }
+    format!(
+        "{}?{}",
+        page_id,
+        serde_urlencoded::to_string(query).unwrap_or_default()
+    )
 }
 
-/// Cynthia doesn't respond to POST requests, but it's plugins might.
-/// Support for form data is planned but not yet implemented.
-#[post("/{a:.*}")]
-pub(crate) async fn post(
+/// The methods Cynthia answers on its own, without plugins. Assets are only ever read.
+fn allowed_methods(req: &HttpRequest) -> &'static str {
+    if req.path().starts_with("/assets/") {
+        "GET, HEAD, OPTIONS"
+    } else {
+        "GET, HEAD, OPTIONS, POST"
+    }
+}
+
+/// Catches every request that isn't a GET or HEAD request. Cynthia doesn't do anything with these
+/// itself, but it's plugins might. They get the request body, with form fields parsed and
+/// uploaded files written to `.cynthiaTemp`.
+///
+/// Unclaimed OPTIONS requests are answered with an `Allow` header, unclaimed POST requests with
+/// `204 No Content` and anything else with `405 Method Not Allowed`.
+pub(crate) async fn dispatch(
     server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
     req: HttpRequest,
-) -> impl Responder {
+    payload: web::Payload,
+) -> HttpResponse {
     let (w_s, w_a) = urlspace();
     // We can't lock the mutex here because it wouldn't be usable by EPS, so we need to use a callback.
     // let mut server_context: MutexGuard<ServerContext> = server_context_mutex.lock().await;
@@ -322,10 +917,10 @@ pub(crate) async fn post(
         })
         .await;
 
-    let page_uri = if req.uri() == "" {
+    let page_uri = if request_uri(&req).is_empty() {
         "root".to_string()
     } else {
-        req.uri().to_string()
+        request_uri(&req)
     };
     let headers = {
         // Transform it into makeshift JSON!
@@ -336,207 +931,467 @@ pub(crate) async fn post(
         serde_json::from_str(&json_kinda).unwrap_or_default()
     };
     trace!("{}", serde_json::to_string(&headers).unwrap());
+    let incoming = match IncomingBody::read(&req, payload).await {
+        Ok(i) => i,
+        Err(e) => return HttpResponse::from_error(e),
+    };
     let pluginsresponse = contact_eps(
         server_context_mutex.clone(),
         EPSRequestBody::WebRequest {
             uri: page_uri.clone(),
             headers,
-            method: "get".to_string(),
+            method: req.method().as_str().to_lowercase(),
+            query: incoming::query(&req),
+            body: incoming.body.clone(),
+            body_base64: incoming.body_base64,
+            form: incoming.form.clone(),
+            files: incoming.files.clone(),
         },
     )
     .await;
-    return match pluginsresponse {
+    // Plugins had their chance to copy uploaded files.
+    incoming.cleanup();
+    let coninfo = req.connection_info().clone();
+    let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+    let uri = {
+        let uri = request_uri(&req);
+        if uri == *"" {
+            "/".to_string()
+        } else {
+            uri
+        }
+    };
+    match pluginsresponse {
         crate::externalpluginservers::EPSResponseBody::WebResponse {
             append_headers,
             response_body,
+            status,
+            body_base64,
+            body_file,
         } => {
-            let coninfo = req.connection_info();
-            let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+            let response = plugin_response(
+                append_headers,
+                response_body,
+                status,
+                body_base64,
+                body_file,
+            );
             config_clone.tell(format!(
                 "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
-                "POST:200".color_ok_green(),
-                {
-                    let uri = req.uri().to_string();
-                    if uri == *"" {
-                        "/".to_string()
-                    } else {
-                        uri
-                    }
-                },
+                status_label(&req, &response),
+                uri,
                 ip.color_lightblue(),
                 "extern".color_pink()
             ));
-            let mut response = HttpResponse::build(actix_web::http::StatusCode::OK);
-            for (k, v) in append_headers {
-                response.append_header((k, v));
-            }
-            response.body(response_body)
+            accesslog::mark(&req, Source::Plugin);
+            response
         }
         crate::externalpluginservers::EPSResponseBody::NoneOk
         | crate::externalpluginservers::EPSResponseBody::Disabled => {
-            HttpResponse::NoContent().finish()
+            let response = match *req.method() {
+                Method::OPTIONS => HttpResponse::NoContent()
+                    .insert_header((header::ALLOW, allowed_methods(&req)))
+                    .finish(),
+                Method::POST => HttpResponse::NoContent().finish(),
+                _ => HttpResponse::MethodNotAllowed()
+                    .insert_header((header::ALLOW, allowed_methods(&req)))
+                    .finish(),
+            };
+            config_clone.tell(format!(
+                "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+                status_label(&req, &response),
+                uri,
+                ip.color_lightblue(),
+                "unclaimed".color_yellow()
+            ));
+            response
         }
         _ => HttpResponse::InternalServerError().body("Internal server error."),
-    };
+    }
 }
+
 #[actix_web::routes]
 #[get("/category/{c:.*}")]
+#[head("/category/{c:.*}")]
 #[get("/c/{c:.*}")]
+#[head("/c/{c:.*}")]
 #[get("/cat/{c:.*}")]
+#[head("/cat/{c:.*}")]
 async fn category(
     server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
     req: HttpRequest,
 ) -> impl Responder {
-    let (w_s, w_a) = urlspace();
-    let c = req.match_info().get("c").unwrap();
-    let virtual_publication = CynthiaPublication::PostList {
-        id: format!("category:{}", c),
-        title: format!("Category: {}", c),
-        short: None,
-        filter: crate::publications::PostListFilter::Category(c.to_string()),
-        scene_override: None,
+    if let Some(response) = claimed(&server_context_mutex, &req).await {
+        return response;
+    }
+    let (c, feed) = feeds::split_feed(req.match_info().get("c").unwrap());
+    let (c, page_number) = split_page(c);
+    let virtual_publication = CynthiaPublication::virtual_category_list(c);
+    virtual_list(
+        server_context_mutex,
+        req,
+        virtual_publication,
+        page_number,
+        feed,
+    )
+    .await
+}
+
+#[actix_web::routes]
+#[get("/tag/{t:.*}")]
+#[head("/tag/{t:.*}")]
+#[get("/t/{t:.*}")]
+#[head("/t/{t:.*}")]
+async fn tags(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Some(response) = claimed(&server_context_mutex, &req).await {
+        return response;
+    }
+    let (t, feed) = feeds::split_feed(req.match_info().get("t").unwrap());
+    let (t, page_number) = split_page(t);
+    let virtual_publication = CynthiaPublication::virtual_tag_list(t);
+    virtual_list(
+        server_context_mutex,
+        req,
+        virtual_publication,
+        page_number,
+        feed,
+    )
+    .await
+}
+
+#[actix_web::routes]
+#[get("/author/{a:.*}")]
+#[head("/author/{a:.*}")]
+#[get("/a/{a:.*}")]
+#[head("/a/{a:.*}")]
+async fn author(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Some(response) = claimed(&server_context_mutex, &req).await {
+        return response;
+    }
+    let (a, feed) = feeds::split_feed(req.match_info().get("a").unwrap());
+    let (a, page_number) = split_page(a);
+    // Author names go into URLs as slugs, anything else is redirected to the slug.
+    let slug = publications::slug(a);
+    if slug != a {
+        let config_clone = server_context_mutex
+            .lock_callback(|a| {
+                a.request_count += 1;
+                a.config.clone()
+            })
+            .await;
+        let location = match (page_number, feed) {
+            (_, Some(kind)) => feeds::feed_path(&format!("/author/{}", slug), kind),
+            (Some(n), None) if n != 1 => format!("/author/{}/page/{}", slug, n),
+            _ => format!("/author/{}", slug),
+        };
+        return moved_permanently(&req, &config_clone, with_query(&req, location));
+    }
+    let published = CynthiaPublicationList::load(server_context_mutex.clone()).await;
+    // The list is titled with the author's name as written in the publications.
+    let name = published
+        .authors()
+        .into_iter()
+        .find(|name| publications::slug(name) == slug)
+        .unwrap_or(slug);
+    let virtual_publication = CynthiaPublication::virtual_author_list(&name);
+    virtual_list(
+        server_context_mutex,
+        req,
+        virtual_publication,
+        page_number,
+        feed,
+    )
+    .await
+}
+
+#[actix_web::routes]
+#[get("/archive/{d:.*}")]
+#[head("/archive/{d:.*}")]
+async fn archive(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Some(response) = claimed(&server_context_mutex, &req).await {
+        return response;
+    }
+    let (d, page_number) = split_page(req.match_info().get("d").unwrap());
+    let mut parts = d.trim_end_matches('/').split('/');
+    let year = parts.next().and_then(|y| y.parse::<i32>().ok());
+    let month = parts
+        .next()
+        .map(|m| m.parse::<u32>().ok().filter(|m| (1..=12).contains(m)));
+    let virtual_publication = match (year, month, parts.next()) {
+        (Some(year), None, None) => CynthiaPublication::virtual_archive_list(year, None),
+        (Some(year), Some(Some(month)), None) => {
+            CynthiaPublication::virtual_archive_list(year, Some(month))
+        }
+        _ => {
+            let config_clone = server_context_mutex
+                .lock_callback(|a| {
+                    a.request_count += 1;
+                    a.config.clone()
+                })
+                .await;
+            let query: QueryParams = incoming::query(&req).into_iter().collect();
+            return not_found(&server_context_mutex, &req, &config_clone, query).await;
+        }
     };
-    // We can't lock the mutex here because it wouldn't be usable by EPS, so we need to use a callback.
-    // let mut server_context: MutexGuard<ServerContext> = server_context_mutex.lock().await;
+    // Months are always written with two digits, `/archive/2024/5` redirects to `/archive/2024/05`.
+    let canonical = virtual_publication.get_url_path();
+    if format!("/archive/{}", d) != canonical {
+        let config_clone = server_context_mutex
+            .lock_callback(|a| {
+                a.request_count += 1;
+                a.config.clone()
+            })
+            .await;
+        let location = match page_number {
+            Some(n) if n != 1 => format!("{}/page/{}", canonical, n),
+            _ => canonical,
+        };
+        return moved_permanently(&req, &config_clone, with_query(&req, location));
+    }
+    virtual_list(
+        server_context_mutex,
+        req,
+        virtual_publication,
+        page_number,
+        None,
+    )
+    .await
+}
+
+#[actix_web::routes]
+#[get("/search")]
+#[head("/search")]
+#[get("/search/page/{n}")]
+#[head("/search/page/{n}")]
+async fn search_page(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Some(response) = claimed(&server_context_mutex, &req).await {
+        return response;
+    }
+    let (_, page_number) = split_page(req.path().trim_matches('/'));
+    let query: QueryParams = incoming::query(&req).into_iter().collect();
+    if req.match_info().get("n").is_some() && page_number.is_none() {
+        let config_clone = server_context_mutex
+            .lock_callback(|a| {
+                a.request_count += 1;
+                a.config.clone()
+            })
+            .await;
+        return not_found(&server_context_mutex, &req, &config_clone, query).await;
+    }
+    let q = query.get("q").cloned().unwrap_or_default();
+    let virtual_publication = CynthiaPublication::virtual_search_list(&q);
+    virtual_list(
+        server_context_mutex,
+        req,
+        virtual_publication,
+        page_number,
+        None,
+    )
+    .await
+}
+
+#[route("/search.json", method = "GET", method = "HEAD")]
+#[doc = r"Search results as JSON, for search widgets that run in the browser."]
+pub(crate) async fn search_json(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Some(response) = claimed(&server_context_mutex, &req).await {
+        return response;
+    }
+    let (w_s, w_a) = urlspace();
     let config_clone = server_context_mutex
         .lock_callback(|a| {
             a.request_count += 1;
             a.config.clone()
         })
         .await;
+    let query: QueryParams = incoming::query(&req).into_iter().collect();
+    let q = query.get("q").cloned().unwrap_or_default();
+    let limit = query
+        .get("limit")
+        .and_then(|l| l.parse::<usize>().ok())
+        .map_or(search::JSON_LIMIT, |l| l.min(search::JSON_MAX_LIMIT));
+    let published = CynthiaPublicationList::load(server_context_mutex.clone()).await;
+    let results: Vec<serde_json::Value> = search::search(&server_context_mutex, &published, &q)
+        .await
+        .into_iter()
+        .take(limit)
+        .map(|(post, score)| {
+            serde_json::json!({
+                "id": post.id,
+                "url": url_path(&post.id),
+                "title": post.title,
+                "short": post.short,
+                "dates": post.dates,
+                "thumbnail": post.thumbnail,
+                "category": post.category,
+                "tags": post.tags,
+                "author": post.author,
+                "score": score,
+            })
+        })
+        .collect();
+    let body = serde_json::json!({ "query": q, "results": results }).to_string();
+    let coninfo = req.connection_info();
+    let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+    config_clone.tell(format!(
+        "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+        format!("{}:200", req.method()).color_ok_green(),
+        request_uri(&req),
+        ip.color_lightblue(),
+        "search".color_yellow()
+    ));
+    HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, "application/json"))
+        .insert_header((
+            header::CACHE_CONTROL,
+            caching::for_lifetime(config_clone.cache.lifetimes.served),
+        ))
+        .body(body)
+}
 
-    let page_id_string = format!(
-        "virtual:{}",
-        serde_json::to_string(&virtual_publication).unwrap()
-    );
-    let page_id = page_id_string.as_str();
-    let page_uri = if req.uri() == "" {
-        "root".to_string()
-    } else {
-        req.uri().to_string()
-    };
-    let headers = {
-        // Transform it into makeshift JSON!
-        let json_kinda = format!("{:?}", &req.headers().iter().collect::<Vec<_>>())
-            .replace("(\"", "[\"")
-            .replace("\")", "\"]");
-        // And then parse it back into an object.
-        serde_json::from_str(&json_kinda).unwrap_or_default()
-    };
-    trace!("{}", serde_json::to_string(&headers).unwrap());
-    let pluginsresponse = contact_eps(
-        server_context_mutex.clone(),
-        EPSRequestBody::WebRequest {
-            uri: page_uri.clone(),
-            headers,
-            method: "get".to_string(),
-        },
-    )
-    .await;
-    match pluginsresponse {
-        crate::externalpluginservers::EPSResponseBody::WebResponse {
-            append_headers,
-            response_body,
-        } => {
-            let coninfo = req.connection_info();
-            let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
-            config_clone.tell(format!(
-                "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
-                "GET:200".color_ok_green(),
-                {
-                    let uri = req.uri().to_string();
-                    if uri == *"" {
-                        "/".to_string()
-                    } else {
-                        uri
-                    }
-                },
-                ip.color_lightblue(),
-                "extern".color_pink()
-            ));
-            let mut response = HttpResponse::build(actix_web::http::StatusCode::OK);
-            for (k, v) in append_headers {
-                response.append_header((k, v));
-            }
-            return response.body(response_body);
-        }
-        crate::externalpluginservers::EPSResponseBody::NoneOk
-        | crate::externalpluginservers::EPSResponseBody::Disabled => (),
-        _ => return HttpResponse::InternalServerError().body("Internal server error."),
+#[route("/{file:sitemap(-[0-9]+)?\\.xml}", method = "GET", method = "HEAD")]
+#[doc = r"Serves the sitemap, or one part of it on sites with more URLs than fit in one."]
+pub(crate) async fn sitemap_xml(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Some(response) = claimed(&server_context_mutex, &req).await {
+        return response;
     }
-    let from_cache: bool;
+    let (w_s, w_a) = urlspace();
+    let config_clone = server_context_mutex
+        .lock_callback(|a| {
+            a.request_count += 1;
+            a.config.clone()
+        })
+        .await;
+    let query: QueryParams = incoming::query(&req).into_iter().collect();
+    let file = req.match_info().get("file").unwrap_or_default();
+    let part = match file {
+        "sitemap.xml" => None,
+        f => match sitemap::split_part(f) {
+            Some(n) => Some(n),
+            None => return not_found(&server_context_mutex, &req, &config_clone, query).await,
+        },
+    };
+    let cache_id = format!("/{}", file);
     let cache_result = server_context_mutex
-        .lock_callback(|servercontext| servercontext.get_cache(page_id, 0))
+        .lock_callback(|servercontext| servercontext.get_cache(&cache_id, 0))
         .await;
-    let page = match cache_result {
-        Some(c) => {
-            from_cache = true;
-            c
-        }
-        None => {
-            from_cache = false;
-            // Now that we're past the EPS, we can lock the mutex for this scope.
-            let page =
-                render_from_pgid(page_id.parse().unwrap(), server_context_mutex.clone()).await;
-            let mut server_context = server_context_mutex.lock().await;
-            server_context
-                .store_cache(
-                    page_id,
-                    page.clone().unwrap().as_bytes(),
-                    config_clone.clone().cache.lifetimes.served,
-                )
-                .unwrap();
-            server_context
-                .get_cache(page_id, config_clone.clone().cache.lifetimes.served)
-                .unwrap_or(CynthiaCacheExtraction(page.unwrap().as_bytes().to_vec(), 0))
-        }
+    let (body, from_cache) = match cache_result {
+        Some(c) => (c.0, true),
+        None => match sitemap::render_sitemap(&server_context_mutex, part).await {
+            Some(xml) => {
+                server_context_mutex
+                    .lock()
+                    .await
+                    .store_cache(
+                        &cache_id,
+                        xml.as_bytes(),
+                        config_clone.cache.lifetimes.served,
+                    )
+                    .unwrap();
+                (xml.into_bytes(), false)
+            }
+            None => return not_found(&server_context_mutex, &req, &config_clone, query).await,
+        },
     };
-
+    if from_cache {
+        accesslog::mark(&req, Source::Cache);
+    }
+    let cache_control = caching::for_lifetime(config_clone.cache.lifetimes.served);
+    let validators = Validators::new(&body, None);
+    let not_modified = validators.not_modified(&req);
     let coninfo = req.connection_info();
     let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
     config_clone.tell(format!(
         "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
-        "GET:200".color_ok_green(),
-        {
-            let uri = req.uri().to_string();
-            if uri == *"" {
-                "/".to_string()
-            } else {
-                uri
-            }
+        if not_modified {
+            format!("{}:304", req.method()).color_ok_green()
+        } else {
+            format!("{}:200", req.method()).color_ok_green()
         },
+        request_uri(&req),
         ip.color_lightblue(),
-        {
-            if from_cache {
-                "cache".color_green()
-            } else {
-                "generated".color_yellow()
-            }
+        if from_cache {
+            "cache".color_green()
+        } else {
+            "sitemap".color_yellow()
         }
     ));
-    HttpResponse::Ok()
-        .append_header(("Content-Type", "text/html; charset=utf-8"))
-        .body(page.0)
+    if not_modified {
+        return validators.not_modified_response(&cache_control);
+    }
+    let mut response = HttpResponse::Ok();
+    response.insert_header((header::CONTENT_TYPE, "application/xml; charset=utf-8"));
+    response.insert_header((header::CACHE_CONTROL, cache_control));
+    validators.apply(&mut response);
+    response.body(body)
 }
 
-#[actix_web::routes]
-#[get("/tag/{t:.*}")]
-#[get("/t/{t:.*}")]
-async fn tags(
+#[route("/robots.txt", method = "GET", method = "HEAD")]
+#[doc = r"Serves `/robots.txt`, generated from the `[site.robots]` configuration."]
+pub(crate) async fn robots_txt(
     server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
     req: HttpRequest,
 ) -> impl Responder {
+    if let Some(response) = claimed(&server_context_mutex, &req).await {
+        return response;
+    }
     let (w_s, w_a) = urlspace();
-    let t = req.match_info().get("t").unwrap();
-    let virtual_publication = CynthiaPublication::PostList {
-        id: format!("tag:{}", t),
-        title: format!("Tag: {}", t),
-        short: None,
-        filter: crate::publications::PostListFilter::Tag(t.to_string()),
-        scene_override: None,
+    let config_clone = server_context_mutex
+        .lock_callback(|a| {
+            a.request_count += 1;
+            a.config.clone()
+        })
+        .await;
+    let Some(body) = sitemap::render_robots_txt(&config_clone) else {
+        let query: QueryParams = incoming::query(&req).into_iter().collect();
+        return not_found(&server_context_mutex, &req, &config_clone, query).await;
     };
-    // We can't lock the mutex here because it wouldn't be usable by EPS, so we need to use a
-    // callback.
+    let coninfo = req.connection_info();
+    let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+    config_clone.tell(format!(
+        "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+        format!("{}:200", req.method()).color_ok_green(),
+        request_uri(&req),
+        ip.color_lightblue(),
+        "robots".color_yellow()
+    ));
+    HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, "text/plain; charset=utf-8"))
+        .insert_header((
+            header::CACHE_CONTROL,
+            caching::for_lifetime(config_clone.cache.lifetimes.served),
+        ))
+        .body(body)
+}
+
+/// Serves page `page_number` of a virtual postlist, like the lists of a tag or category, or its
+/// `feed`. Routes check whether a plugin or publication [`claimed`] the request first.
+async fn virtual_list(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    req: HttpRequest,
+    virtual_publication: CynthiaPublication,
+    page_number: Option<usize>,
+    feed: Option<FeedKind>,
+) -> HttpResponse {
+    let (w_s, w_a) = urlspace();
+    // We can't lock the mutex here because it wouldn't be usable by EPS, so we need to use a callback.
     // let mut server_context: MutexGuard<ServerContext> = server_context_mutex.lock().await;
     let config_clone = server_context_mutex
         .lock_callback(|a| {
@@ -544,69 +1399,57 @@ async fn tags(
             a.config.clone()
         })
         .await;
-    let page_id_string = format!(
-        "virtual:{}",
-        serde_json::to_string(&virtual_publication).unwrap()
-    );
+
+    let page_id_string = virtual_publication.virtual_id();
     let page_id = page_id_string.as_str();
-    let page_uri = if req.uri() == "" {
-        "root".to_string()
-    } else {
-        req.uri().to_string()
-    };
-    let headers = {
-        // Transform it into makeshift JSON!
-        let json_kinda = format!("{:?}", &req.headers().iter().collect::<Vec<_>>())
-            .replace("(\"", "[\"")
-            .replace("\")", "\"]");
-        // And then parse it back into an object.
-        serde_json::from_str(&json_kinda).unwrap_or_default()
-    };
-    trace!("{}", serde_json::to_string(&headers).unwrap());
-    let pluginsresponse = contact_eps(
-        server_context_mutex.clone(),
-        EPSRequestBody::WebRequest {
-            uri: page_uri.clone(),
-            headers,
-            method: "get".to_string(),
-        },
-    )
-    .await;
-    match pluginsresponse {
-        crate::externalpluginservers::EPSResponseBody::WebResponse {
-            append_headers,
-            response_body,
-        } => {
-            let coninfo = req.connection_info();
-            let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
-            config_clone.tell(format!(
-                "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
-                "GET:200".color_ok_green(),
-                {
-                    let uri = req.uri().to_string();
-                    if uri == *"" {
-                        "/".to_string()
-                    } else {
-                        uri
-                    }
-                },
-                ip.color_lightblue(),
-                "extern".color_pink()
-            ));
-            let mut response = HttpResponse::build(actix_web::http::StatusCode::OK);
-            for (k, v) in append_headers {
-                response.append_header((k, v));
-            }
-            return response.body(response_body);
+    let query: QueryParams = incoming::query(&req).into_iter().collect();
+    let cache_id = render_cache_id(
+        page_id,
+        page_number.unwrap_or(1),
+        &query,
+        config_clone.cache.include_query,
+    );
+    if let Some(kind) = feed {
+        // Feeds aren't paginated.
+        if page_number.is_some() {
+            return not_found(&server_context_mutex, &req, &config_clone, query).await;
         }
-        crate::externalpluginservers::EPSResponseBody::NoneOk
-        | crate::externalpluginservers::EPSResponseBody::Disabled => (),
-        _ => return HttpResponse::InternalServerError().body("Internal server error."),
+        return self::feed(
+            &server_context_mutex,
+            &req,
+            &config_clone,
+            virtual_publication,
+            kind,
+        )
+        .await;
+    }
+    if page_number == Some(1) {
+        let location = virtual_publication.get_url_path();
+        // Search results carry their query in their path already.
+        let location = if location.contains('?') {
+            location
+        } else {
+            with_query(&req, location)
+        };
+        return moved_permanently(&req, &config_clone, location);
     }
+    // Every search is a list of its own, caching them would let anyone grow the cache without
+    // bounds.
+    let cacheable = !matches!(
+        virtual_publication,
+        CynthiaPublication::PostList {
+            filter: PostListFilter::Search(_),
+            ..
+        }
+    );
     let from_cache: bool;
-    let cache_result = server_context_mutex
-        .lock_callback(|servercontext| servercontext.get_cache(page_id, 0))
-        .await;
+    let cache_result = if cacheable {
+        server_context_mutex
+            .lock_callback(|servercontext| servercontext.get_cache(&cache_id, 0))
+            .await
+    } else {
+        None
+    };
     let page = match cache_result {
         Some(c) => {
             from_cache = true;
@@ -615,29 +1458,63 @@ async fn tags(
         None => {
             from_cache = false;
             // Now that we're past the EPS, we can lock the mutex for this scope.
-            let page =
-                render_from_pgid(page_id.parse().unwrap(), server_context_mutex.clone()).await;
-            let mut server_context = server_context_mutex.lock().await;
-            server_context
-                .store_cache(
-                    page_id,
-                    page.clone().unwrap().as_bytes(),
-                    config_clone.clone().cache.lifetimes.served,
-                )
-                .unwrap();
-            server_context
-                .get_cache(page_id, config_clone.clone().cache.lifetimes.served)
-                .unwrap_or(CynthiaCacheExtraction(page.unwrap().as_bytes().to_vec(), 0))
+            let page = render_from_pgid(
+                page_id.to_string(),
+                page_number.unwrap_or(1),
+                query.clone(),
+                server_context_mutex.clone(),
+            )
+            .await;
+            if page.is_not_found() {
+                return not_found(&server_context_mutex, &req, &config_clone, query).await;
+            }
+            if cacheable {
+                let mut server_context = server_context_mutex.lock().await;
+                server_context
+                    .store_cache(
+                        &cache_id,
+                        page.clone().unwrap().as_bytes(),
+                        config_clone.clone().cache.lifetimes.served,
+                    )
+                    .unwrap();
+            }
+            // Reading it back would count as a cache hit.
+            CynthiaCacheExtraction(page.unwrap().into_bytes(), 0, None)
         }
     };
+    if from_cache {
+        accesslog::mark(&req, Source::Cache);
+    }
+    let cache_control = caching::for_lifetime(config_clone.cache.lifetimes.served);
+    let compressed = match Encoding::negotiate(&req) {
+        Some(encoding) => compression::compressed_variant(
+            &server_context_mutex,
+            &cache_id,
+            encoding,
+            &page.0,
+            None,
+            true,
+        )
+        .await
+        .map(|body| (encoding, body)),
+        None => None,
+    };
+    // A list changes whenever any of its posts does, so it only gets an ETag.
+    let validators = Validators::new(&page.0, None)
+        .for_encoding(compressed.as_ref().map(|(encoding, _)| *encoding));
+    let not_modified = validators.not_modified(&req);
 
     let coninfo = req.connection_info();
     let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
     config_clone.tell(format!(
         "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
-        "GET:200".color_ok_green(),
+        if not_modified {
+            format!("{}:304", req.method()).color_ok_green()
+        } else {
+            format!("{}:200", req.method()).color_ok_green()
+        },
         {
-            let uri = req.uri().to_string();
+            let uri = request_uri(&req);
             if uri == *"" {
                 "/".to_string()
             } else {
@@ -653,7 +1530,60 @@ async fn tags(
             }
         }
     ));
-    HttpResponse::Ok()
-        .append_header(("Content-Type", "text/html; charset=utf-8"))
-        .body(page.0)
+    // Virtual lists are never the 404 page, so only their own directives or the default apply.
+    let robots = virtual_publication
+        .get_options()
+        .and_then(|o| o.robots())
+        .unwrap_or_else(|| config_clone.site.robots.directives.clone());
+    if not_modified {
+        return validators.not_modified_response(&cache_control);
+    }
+    let mut response = HttpResponse::Ok();
+    response.append_header(("Content-Type", "text/html; charset=utf-8"));
+    response.insert_header((header::VARY, "Accept-Encoding"));
+    response.insert_header((header::CACHE_CONTROL, cache_control));
+    response.insert_header(("X-Robots-Tag", robots));
+    validators.apply(&mut response);
+    match compressed {
+        Some((encoding, body)) => {
+            response.insert_header((header::CONTENT_ENCODING, encoding.name()));
+            response.body(body)
+        }
+        None => response.body(page.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_paths() {
+        // The paths `paginate` in renders.rs links to, without their leading slash.
+        assert_eq!(split_page("posts/page/2"), ("posts", Some(2)));
+        assert_eq!(split_page("posts/page/2/"), ("posts", Some(2)));
+        assert_eq!(split_page("tag/rust/page/3"), ("tag/rust", Some(3)));
+        assert_eq!(split_page("posts"), ("posts", None));
+        assert_eq!(split_page("posts/"), ("posts/", None));
+        // Page 0 is parsed, so it can be answered with a 404 rather than the first page.
+        assert_eq!(split_page("posts/page/0"), ("posts", Some(0)));
+    }
+
+    #[test]
+    fn not_page_paths() {
+        assert_eq!(split_page("posts/page/x"), ("posts/page/x", None));
+        assert_eq!(split_page("posts/page/"), ("posts/page/", None));
+        assert_eq!(split_page("posts/page/02"), ("posts/page/02", None));
+        assert_eq!(split_page("posts/page/-1"), ("posts/page/-1", None));
+    }
+
+    #[test]
+    fn root_page_paths() {
+        // The root postlist is on `/`, so its pages are on `/page/{n}`.
+        assert_eq!(split_page("page/2"), ("", Some(2)));
+        assert_eq!(split_page("page/2/"), ("", Some(2)));
+        assert_eq!(split_page("page/x"), ("page/x", None));
+        assert_eq!(split_page("page"), ("page", None));
+        assert_eq!(split_page("page/"), ("page/", None));
+    }
 }
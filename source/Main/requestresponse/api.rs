@@ -0,0 +1,369 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+
+//! A read-only JSON API over the publications, for using Cynthia as a headless CMS.
+//!
+//! - `/api/publications`: Every publication, or the posts matching `filter`.
+//! - `/api/publications/{id}`: A single publication.
+//! - `/api/tags` and `/api/categories`: Every tag or category, with how many posts use it.
+//!
+//! Adding `content=true` to the query includes the rendered content of pages and posts, and the
+//! posts of postlists.
+
+use std::sync::Arc;
+
+use actix_web::http::{header, StatusCode};
+use actix_web::web::Data;
+use actix_web::{HttpRequest, HttpResponse, Responder};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+
+use super::{caching, claimed, incoming, request_uri, serve_request, status_label, urlspace};
+use crate::config::CynthiaConfClone;
+use crate::publications::{
+    url_path, CynthiaPublication, CynthiaPublicationList, CynthiaPublicationListTrait,
+    PostListFilter, PostLists, PostPublication,
+};
+use crate::renders::{self, QueryParams};
+use crate::tell::CynthiaColors;
+use crate::{LockCallback, ServerContext};
+
+type ApiResult = Result<Value, (StatusCode, String)>;
+
+#[actix_web::routes]
+#[get("/api/publications")]
+#[head("/api/publications")]
+pub(crate) async fn api_publications(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    req: HttpRequest,
+) -> impl Responder {
+    let (config_clone, query) = match enabled(&server_context_mutex, &req).await {
+        Ok(enabled) => enabled,
+        Err(response) => return response,
+    };
+    let published = CynthiaPublicationList::load(server_context_mutex.clone()).await;
+    let content = wants_content(&query);
+    let result: ApiResult = async {
+        let offset = number(&query, "offset")?.unwrap_or(0);
+        let limit = number(&query, "limit")?.unwrap_or(usize::MAX);
+        // Only the requested page of publications is turned into JSON, rendering content is slow.
+        let mut publications: Vec<Value> = vec![];
+        let total = match query.get("filter") {
+            Some(filter) => {
+                let filter = parse_filter(filter).ok_or((
+                    StatusCode::BAD_REQUEST,
+                    format!("Invalid filter `{}`.", filter),
+                ))?;
+                let posts = renders::list_posts(filter, &published, &server_context_mutex).await;
+                for post in posts.iter().skip(offset).take(limit) {
+                    publications.push(post_json(post, content).await);
+                }
+                posts.len()
+            }
+            None => {
+                for publication in published.iter().skip(offset).take(limit) {
+                    publications.push(
+                        publication_json(publication, &published, &server_context_mutex, content)
+                            .await,
+                    );
+                }
+                published.len()
+            }
+        };
+        Ok(json!({ "total": total, "publications": publications }))
+    }
+    .await;
+    respond(&req, &config_clone, result)
+}
+
+#[actix_web::routes]
+#[get("/api/publications/{id:.*}")]
+#[head("/api/publications/{id:.*}")]
+pub(crate) async fn api_publication(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    req: HttpRequest,
+) -> impl Responder {
+    let (config_clone, query) = match enabled(&server_context_mutex, &req).await {
+        Ok(enabled) => enabled,
+        Err(response) => return response,
+    };
+    let published = CynthiaPublicationList::load(server_context_mutex.clone()).await;
+    let id = req
+        .match_info()
+        .get("id")
+        .unwrap_or_default()
+        .trim_matches('/');
+    // Aliases and differently cased ids lead to the publication they belong to. Virtual
+    // publications can't be looked up, they only exist on their own routes.
+    let publication = match id {
+        "" | "root" => published.get_root(),
+        id if id.starts_with("virtual:") => None,
+        id => published
+            .canonical_id(id, config_clone.site.case_insensitive)
+            .and_then(|id| published.get_by_id(id)),
+    };
+    let result: ApiResult = match publication {
+        Some(publication) => Ok(publication_json(
+            &publication,
+            &published,
+            &server_context_mutex,
+            wants_content(&query),
+        )
+        .await),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            format!("No publication with id `{}`.", id),
+        )),
+    };
+    respond(&req, &config_clone, result)
+}
+
+#[actix_web::routes]
+#[get("/api/tags")]
+#[head("/api/tags")]
+pub(crate) async fn api_tags(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    req: HttpRequest,
+) -> impl Responder {
+    let (config_clone, _) = match enabled(&server_context_mutex, &req).await {
+        Ok(enabled) => enabled,
+        Err(response) => return response,
+    };
+    let published = CynthiaPublicationList::load(server_context_mutex.clone()).await;
+    let posts = published.only_posts();
+    let tags: Vec<Value> = published
+        .tags()
+        .into_iter()
+        .map(|tag| {
+            let count = posts
+                .filter(PostListFilter::Tag(tag.clone()), &config_clone.site)
+                .len();
+            json!({
+                "name": tag,
+                "url": CynthiaPublication::virtual_tag_list(&tag).get_url_path(),
+                "count": count,
+            })
+        })
+        .collect();
+    respond(&req, &config_clone, Ok(json!({ "tags": tags })))
+}
+
+#[actix_web::routes]
+#[get("/api/categories")]
+#[head("/api/categories")]
+pub(crate) async fn api_categories(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    req: HttpRequest,
+) -> impl Responder {
+    let (config_clone, _) = match enabled(&server_context_mutex, &req).await {
+        Ok(enabled) => enabled,
+        Err(response) => return response,
+    };
+    let published = CynthiaPublicationList::load(server_context_mutex.clone()).await;
+    let posts = published.only_posts();
+    let categories: Vec<Value> = published
+        .categories()
+        .into_iter()
+        .map(|category| {
+            let count = posts
+                .filter(
+                    PostListFilter::Category(category.clone()),
+                    &config_clone.site,
+                )
+                .len();
+            json!({
+                "name": category,
+                "url": CynthiaPublication::virtual_category_list(&category).get_url_path(),
+                "count": count,
+            })
+        })
+        .collect();
+    respond(&req, &config_clone, Ok(json!({ "categories": categories })))
+}
+
+/// Counts the request, and returns the configuration and query if the API answers it. A disabled
+/// API isn't there at all, and plugins and publications on the same path come first, those
+/// requests are served like any other instead.
+async fn enabled(
+    server_context_mutex: &Data<Arc<Mutex<ServerContext>>>,
+    req: &HttpRequest,
+) -> Result<(CynthiaConfClone, QueryParams), HttpResponse> {
+    let config_clone = server_context_mutex
+        .lock_callback(|a| a.config.clone())
+        .await;
+    if !config_clone.site.meta.enable_api {
+        return Err(serve_request(server_context_mutex.clone(), req.clone()).await);
+    }
+    if let Some(response) = claimed(server_context_mutex, req).await {
+        return Err(response);
+    }
+    server_context_mutex
+        .lock_callback(|a| a.request_count += 1)
+        .await;
+    let query: QueryParams = incoming::query(req).into_iter().collect();
+    Ok((config_clone, query))
+}
+
+fn respond(req: &HttpRequest, config_clone: &CynthiaConfClone, result: ApiResult) -> HttpResponse {
+    let (w_s, w_a) = urlspace();
+    let (status, body) = match result {
+        Ok(body) => (StatusCode::OK, body),
+        Err((status, error)) => (status, json!({ "error": error })),
+    };
+    let response = HttpResponse::build(status)
+        .insert_header((header::CONTENT_TYPE, "application/json"))
+        .insert_header((
+            header::CACHE_CONTROL,
+            caching::for_lifetime(config_clone.cache.lifetimes.served),
+        ))
+        // The API is read-only and everything in it is public already, so any site may use it.
+        .insert_header((header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"))
+        .body(body.to_string());
+    let coninfo = req.connection_info();
+    let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+    config_clone.tell(format!(
+        "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+        status_label(req, &response),
+        request_uri(req),
+        ip.color_lightblue(),
+        "api".color_yellow()
+    ));
+    response
+}
+
+/// Reads `filter` like the filter of a postlist in the publication file: either its JSON, like
+/// `{"archive":{"year":2024}}`, a plain name like `latest`, or `{name}:{value}` like `tag:rust`.
+fn parse_filter(filter: &str) -> Option<PostListFilter> {
+    if let Ok(f) = serde_json::from_str(filter) {
+        return Some(f);
+    }
+    let value = match filter.split_once(':') {
+        Some((name, value)) => json!({ name: value }),
+        None => json!(filter),
+    };
+    serde_json::from_value(value).ok()
+}
+
+fn wants_content(query: &QueryParams) -> bool {
+    matches!(
+        query.get("content").map(String::as_str),
+        Some("true" | "1" | "yes")
+    )
+}
+
+fn number(query: &QueryParams, name: &str) -> Result<Option<usize>, (StatusCode, String)> {
+    query
+        .get(name)
+        .map(|n| {
+            n.parse().map_err(|_| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    format!("`{}` should be a number, not `{}`.", name, n),
+                )
+            })
+        })
+        .transpose()
+}
+
+async fn publication_json(
+    publication: &CynthiaPublication,
+    published: &CynthiaPublicationList,
+    server_context_mutex: &Data<Arc<Mutex<ServerContext>>>,
+    content: bool,
+) -> Value {
+    let mut value = match publication {
+        CynthiaPublication::Page {
+            id,
+            title,
+            description,
+            thumbnail,
+            dates,
+            pagecontent,
+            ..
+        } => {
+            let mut value = json!({
+                "kind": "page",
+                "id": id,
+                "url": url_path(id),
+                "title": title,
+                "description": description,
+                "dates": dates,
+                "thumbnail": thumbnail,
+            });
+            if content {
+                value["content"] = renders::render_content(pagecontent.clone()).await.into();
+            }
+            value
+        }
+        // Posts look the same here as they do in lists of posts.
+        CynthiaPublication::Post { id, .. } => {
+            match published
+                .only_posts()
+                .into_iter()
+                .find(|post| &post.id == id)
+            {
+                Some(post) => post_json(&post, content).await,
+                None => json!({}),
+            }
+        }
+        CynthiaPublication::PostList {
+            id,
+            title,
+            short,
+            filter,
+            ..
+        } => {
+            let mut value = json!({
+                "kind": "postlist",
+                "id": id,
+                "url": url_path(id),
+                "title": title,
+                "short": short,
+                "filter": filter,
+            });
+            if content {
+                let mut posts = vec![];
+                for post in
+                    renders::list_posts(filter.clone(), published, server_context_mutex).await
+                {
+                    posts.push(post_json(&post, false).await);
+                }
+                value["posts"] = posts.into();
+            }
+            value
+        }
+        CynthiaPublication::Redirect { id, status, .. } => json!({
+            "kind": "redirect",
+            "id": id,
+            "url": url_path(id),
+            "target": publication.get_redirect().map(|(location, _)| location),
+            "status": status,
+        }),
+    };
+    value["aliases"] = publication.get_aliases().into();
+    value
+}
+
+async fn post_json(post: &PostPublication, content: bool) -> Value {
+    let mut value = json!({
+        "kind": "post",
+        "id": post.id,
+        "url": url_path(&post.id),
+        "title": post.title,
+        "short": post.short,
+        "dates": post.dates,
+        "thumbnail": post.thumbnail,
+        "category": post.category,
+        "tags": post.tags,
+        "author": post.author,
+    });
+    if content {
+        value["content"] = renders::render_content(post.postcontent.clone())
+            .await
+            .into();
+    }
+    value
+}
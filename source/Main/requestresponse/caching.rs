@@ -0,0 +1,89 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+
+//! `Cache-Control` headers for browsers and CDNs, derived from the `cache.lifetimes` config.
+
+use std::path::Path;
+
+use crate::config::Lifetimes;
+
+/// Fingerprinted files never change under the same name, so they may be cached for a year.
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// A `Cache-Control` value for a lifetime in seconds. Caches may serve a stale copy for another
+/// lifetime while they revalidate it.
+pub(crate) fn for_lifetime(lifetime: u64) -> String {
+    if lifetime == 0 {
+        String::from("no-cache")
+    } else {
+        format!("public, max-age={lifetime}, stale-while-revalidate={lifetime}")
+    }
+}
+
+/// The `Cache-Control` value for an asset, based on its type, or `immutable` for fingerprinted files.
+pub(crate) fn for_asset(lifetimes: &Lifetimes, path: &Path, content_type: &str) -> String {
+    if fingerprinted(path) {
+        return String::from(IMMUTABLE);
+    }
+    let lifetime = match content_type.split(';').next().unwrap_or("").trim() {
+        "text/css" => lifetimes.stylesheets,
+        "text/javascript" | "application/javascript" => lifetimes.javascript,
+        _ => lifetimes.assets,
+    };
+    for_lifetime(lifetime)
+}
+
+/// Whether a file name contains a content hash, like `app.3f2a9c1b.js` or `style-8d9e0f1a2b.css`.
+/// A hash has both digits and the letters `a` to `f`, so dates and counters, like in
+/// `IMG_20240315.jpg`, aren't taken for one.
+fn fingerprinted(path: &Path) -> bool {
+    let Some(stem) = path.file_stem().and_then(|s| s.to_str()) else {
+        return false;
+    };
+    stem.split(['.', '-', '_']).skip(1).any(|part| {
+        part.len() >= 8
+            && part.chars().all(|c| c.is_ascii_hexdigit())
+            && part.chars().any(|c| c.is_ascii_digit())
+            && part.chars().any(|c| c.is_ascii_alphabetic())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprints() {
+        for name in [
+            "app.3f2a9c1b.js",
+            "style-8d9e0f1a2b.css",
+            "chunk_0a1b2c3d4e5f.js",
+            "logo.ABCDEF12.svg",
+        ] {
+            assert!(fingerprinted(Path::new(name)), "{name}");
+        }
+    }
+
+    #[test]
+    fn not_fingerprints() {
+        for name in [
+            // Dates, counters and other plain numbers.
+            "IMG_20240315.jpg",
+            "photo-12345678.png",
+            "report.2024.pdf",
+            // Too short, or not hexadecimal.
+            "app.3f2a9c.js",
+            "style-deadbeefz1.css",
+            // Only letters, like a word.
+            "fonts-deadbeef.woff2",
+            // The hash has to follow the name.
+            "3f2a9c1b.js",
+            "app.js",
+        ] {
+            assert!(!fingerprinted(Path::new(name)), "{name}");
+        }
+    }
+}
@@ -0,0 +1,220 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+
+//! Response compression. Compressed bodies are cached as variants of the uncompressed cache object,
+//! so every response is only compressed once for as long as it is cached.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use actix_web::http::header;
+use actix_web::web::Data;
+use actix_web::HttpRequest;
+use log::warn;
+use tokio::sync::Mutex;
+
+use crate::ServerContext;
+
+/// Bodies smaller than this aren't worth compressing.
+const MIN_SIZE: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    /// The content coding, as used in `Accept-Encoding` and `Content-Encoding`.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// The file extension of a precompressed sibling file.
+    fn extension(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gz",
+        }
+    }
+
+    /// The precompressed sibling of a file, like `style.css.br` for `style.css`.
+    pub(crate) fn sibling(&self, path: &Path) -> PathBuf {
+        let mut sibling = path.as_os_str().to_owned();
+        sibling.push(".");
+        sibling.push(self.extension());
+        PathBuf::from(sibling)
+    }
+
+    fn compress(&self, contents: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut output = Vec::new();
+                {
+                    let mut writer = brotli::CompressorWriter::new(&mut output, 4096, 9, 22);
+                    writer.write_all(contents)?;
+                }
+                Ok(output)
+            }
+            Encoding::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(contents)?;
+                encoder.finish()
+            }
+        }
+    }
+
+    /// Picks the preferred encoding from the request's `Accept-Encoding` header. Brotli wins over
+    /// gzip when the client likes them equally.
+    pub(crate) fn negotiate(req: &HttpRequest) -> Option<Encoding> {
+        let accept = req.headers().get(header::ACCEPT_ENCODING)?.to_str().ok()?;
+        let mut best: Option<(Encoding, f32)> = None;
+        for encoding in [Encoding::Brotli, Encoding::Gzip] {
+            let quality = accept
+                .split(',')
+                .filter_map(|item| {
+                    let mut parts = item.split(';');
+                    let name = parts.next()?.trim();
+                    // Content codings are case-insensitive.
+                    if !name.eq_ignore_ascii_case(encoding.name()) && name != "*" {
+                        return None;
+                    }
+                    let quality = parts
+                        .find_map(|p| p.trim().strip_prefix("q="))
+                        .and_then(|q| q.trim().parse::<f32>().ok())
+                        .unwrap_or(1.0);
+                    // An explicit entry takes precedence over the wildcard.
+                    Some((name == "*", quality))
+                })
+                .min_by(|a, b| a.0.cmp(&b.0))
+                .map(|(_, q)| q)
+                .unwrap_or(0.0);
+            if quality > 0.0 && best.map(|(_, q)| quality > q).unwrap_or(true) {
+                best = Some((encoding, quality));
+            }
+        }
+        best.map(|(encoding, _)| encoding)
+    }
+}
+
+/// Whether a content type benefits from compression. Images, audio and video are compressed
+/// already.
+pub(crate) fn compressible(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or("").trim();
+    essence.starts_with("text/")
+        || essence.ends_with("+xml")
+        || essence.ends_with("+json")
+        || matches!(
+            essence,
+            "application/javascript"
+                | "application/json"
+                | "application/xml"
+                | "application/wasm"
+                | "image/svg+xml"
+        )
+}
+
+/// Returns the body of cache object `cache_id` compressed with `encoding`. A precompressed `sibling`
+/// file is used as-is when it exists. Otherwise `contents` are compressed, but only if
+/// `compress` allows it. The result is stored as a cache variant, so it is only made once.
+pub(crate) async fn compressed_variant(
+    server_context_mutex: &Data<Arc<Mutex<ServerContext>>>,
+    cache_id: &str,
+    encoding: Encoding,
+    contents: &[u8],
+    sibling: Option<PathBuf>,
+    compress: bool,
+) -> Option<Vec<u8>> {
+    if let Some(variant) = server_context_mutex
+        .lock()
+        .await
+        .get_cache_variant(cache_id, encoding.name())
+    {
+        return Some(variant);
+    }
+    let variant = match sibling.filter(|s| s.is_file()) {
+        Some(sibling) => match std::fs::read(&sibling) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Could not read {}: {}", sibling.display(), e);
+                return None;
+            }
+        },
+        None if compress && contents.len() >= MIN_SIZE => match encoding.compress(contents) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Could not compress {}: {}", cache_id, e);
+                return None;
+            }
+        },
+        None => return None,
+    };
+    server_context_mutex
+        .lock()
+        .await
+        .store_cache_variant(cache_id, encoding.name(), &variant);
+    Some(variant)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn negotiate(accept: Option<&str>) -> Option<Encoding> {
+        let req = match accept {
+            Some(accept) => TestRequest::get().insert_header((header::ACCEPT_ENCODING, accept)),
+            None => TestRequest::get(),
+        };
+        Encoding::negotiate(&req.to_http_request())
+    }
+
+    #[test]
+    fn preferred_encodings() {
+        assert_eq!(negotiate(None), None);
+        assert_eq!(negotiate(Some("")), None);
+        assert_eq!(negotiate(Some("gzip")), Some(Encoding::Gzip));
+        assert_eq!(negotiate(Some("GZip")), Some(Encoding::Gzip));
+        assert_eq!(negotiate(Some("deflate, zstd")), None);
+        // Brotli wins ties.
+        assert_eq!(negotiate(Some("gzip, deflate, br")), Some(Encoding::Brotli));
+    }
+
+    #[test]
+    fn quality_values() {
+        assert_eq!(
+            negotiate(Some("br;q=0.5, gzip;q=0.8")),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(
+            negotiate(Some("br; q=0.9 , gzip ;q=0.8")),
+            Some(Encoding::Brotli)
+        );
+        assert_eq!(negotiate(Some("gzip;q=0")), None);
+        assert_eq!(negotiate(Some("br;q=0, gzip")), Some(Encoding::Gzip));
+        // Refusing an uncompressed response doesn't change which compression is picked.
+        assert_eq!(negotiate(Some("identity;q=0")), None);
+        assert_eq!(negotiate(Some("identity;q=0, gzip")), Some(Encoding::Gzip));
+    }
+
+    #[test]
+    fn wildcards() {
+        assert_eq!(negotiate(Some("*")), Some(Encoding::Brotli));
+        assert_eq!(negotiate(Some("*;q=0")), None);
+        // Explicit entries take precedence over the wildcard, wherever they are.
+        assert_eq!(negotiate(Some("*, br;q=0")), Some(Encoding::Gzip));
+        assert_eq!(negotiate(Some("br;q=0, *")), Some(Encoding::Gzip));
+        assert_eq!(
+            negotiate(Some("gzip;q=0.2, *;q=0.5")),
+            Some(Encoding::Brotli)
+        );
+    }
+}
@@ -0,0 +1,180 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+
+//! Conditional requests: `ETag`/`Last-Modified` validators and `304 Not Modified` answers.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::http::header::{
+    self, ETag, EntityTag, Header, IfModifiedSince, IfNoneMatch, LastModified,
+};
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
+use sha2::{Digest, Sha256};
+
+use super::compression::Encoding;
+
+/// The validators a response is sent with.
+pub(crate) struct Validators {
+    pub(crate) etag: EntityTag,
+    pub(crate) last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    /// Creates validators with a strong ETag computed from the response body.
+    pub(crate) fn new(body: &[u8], last_modified: Option<SystemTime>) -> Self {
+        let digest = Sha256::digest(body);
+        // Half of a SHA-256 is still plenty to tell two versions of a file apart.
+        let tag = digest[..16]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        Validators {
+            etag: EntityTag::new_strong(tag),
+            last_modified,
+        }
+    }
+
+    /// Validators for files that are too large to hash on every request. The ETag is derived from
+    /// the file size and modification time instead.
+    pub(crate) fn from_metadata(length: u64, modified: Option<SystemTime>) -> Self {
+        let nanos = modified
+            .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        Validators {
+            etag: EntityTag::new_strong(format!("{:x}-{:x}", length, nanos)),
+            last_modified: modified,
+        }
+    }
+
+    /// Gives a compressed representation its own ETag, as its bytes differ from the original.
+    pub(crate) fn for_encoding(self, encoding: Option<Encoding>) -> Self {
+        match encoding {
+            None => self,
+            Some(encoding) => Validators {
+                etag: EntityTag::new_strong(format!("{}-{}", self.etag.tag(), encoding.name())),
+                last_modified: self.last_modified,
+            },
+        }
+    }
+
+    /// Checks the request's `If-None-Match` and `If-Modified-Since` headers against these
+    /// validators. `If-Modified-Since` is only considered without `If-None-Match`, as per RFC 9110.
+    pub(crate) fn not_modified(&self, req: &HttpRequest) -> bool {
+        if req.headers().contains_key(IfNoneMatch::name()) {
+            return match IfNoneMatch::parse(req) {
+                Ok(IfNoneMatch::Any) => true,
+                Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|t| t.weak_eq(&self.etag)),
+                Err(_) => false,
+            };
+        }
+        match (IfModifiedSince::parse(req), self.last_modified) {
+            (Ok(IfModifiedSince(since)), Some(modified)) => {
+                // HTTP dates have a precision of one second, so compare whole seconds only.
+                let secs = |t: SystemTime| t.duration_since(UNIX_EPOCH).map(|d| d.as_secs());
+                match (secs(modified), secs(since.into())) {
+                    (Ok(modified), Ok(since)) => modified <= since,
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// Adds the `ETag` and `Last-Modified` headers to a response.
+    pub(crate) fn apply(&self, response: &mut HttpResponseBuilder) {
+        response.insert_header(ETag(self.etag.clone()));
+        if let Some(modified) = self.last_modified {
+            response.insert_header(LastModified(modified.into()));
+        }
+    }
+
+    /// A bodiless `304 Not Modified` response carrying the validators. The `Cache-Control` header
+    /// is repeated, as it would have been sent with a full response.
+    pub(crate) fn not_modified_response(&self, cache_control: &str) -> HttpResponse {
+        let mut response = HttpResponse::NotModified();
+        self.apply(&mut response);
+        response.insert_header((header::CACHE_CONTROL, cache_control));
+        response.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::HttpDate;
+    use actix_web::test::TestRequest;
+    use std::time::Duration;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn request(headers: &[(&str, String)]) -> HttpRequest {
+        headers
+            .iter()
+            .fold(TestRequest::get(), |r, (name, value)| {
+                r.insert_header((*name, value.as_str()))
+            })
+            .to_http_request()
+    }
+
+    #[test]
+    fn etags() {
+        let validators = Validators::new(b"body", None);
+        let tag = validators.etag.tag().to_string();
+        let matches =
+            |value: String| validators.not_modified(&request(&[("If-None-Match", value)]));
+        assert!(matches(format!("\"{tag}\"")));
+        assert!(matches(format!("\"other\", \"{tag}\"")));
+        assert!(matches(String::from("*")));
+        assert!(!matches(String::from("\"other\"")));
+        assert!(!matches(String::from("not an etag")));
+        // `If-None-Match` uses the weak comparison, so a weak tag matches the strong one.
+        assert!(matches(format!("W/\"{tag}\"")));
+        // A compressed representation has its own tag.
+        let gzip = Validators::new(b"body", None).for_encoding(Some(Encoding::Gzip));
+        assert!(!gzip.not_modified(&request(&[("If-None-Match", format!("\"{tag}\""))])));
+    }
+
+    #[test]
+    fn modification_dates() {
+        let validators = Validators::new(b"body", Some(at(1_700_000_000)));
+        let since = |secs: u64| {
+            validators.not_modified(&request(&[(
+                "If-Modified-Since",
+                HttpDate::from(at(secs)).to_string(),
+            )]))
+        };
+        assert!(since(1_700_000_000));
+        assert!(since(1_700_000_100));
+        assert!(!since(1_699_999_999));
+        // Without a modification date, there's nothing to compare with.
+        let undated = Validators::new(b"body", None);
+        assert!(!undated.not_modified(&request(&[(
+            "If-Modified-Since",
+            HttpDate::from(at(1_700_000_000)).to_string(),
+        )])));
+    }
+
+    #[test]
+    fn etags_win_over_dates() {
+        let validators = Validators::new(b"body", Some(at(1_700_000_000)));
+        let tag = validators.etag.tag().to_string();
+        let recent = HttpDate::from(at(1_700_000_100)).to_string();
+        let old = HttpDate::from(at(1_600_000_000)).to_string();
+        // A changed ETag means modified, however recent `If-Modified-Since` is.
+        assert!(!validators.not_modified(&request(&[
+            ("If-None-Match", String::from("\"other\"")),
+            ("If-Modified-Since", recent),
+        ])));
+        // And a matching ETag means not modified, however old it is.
+        assert!(validators.not_modified(&request(&[
+            ("If-None-Match", format!("\"{tag}\"")),
+            ("If-Modified-Since", old),
+        ])));
+    }
+}
@@ -0,0 +1,208 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+
+//! Reading request bodies for plugins: the raw body, form fields and uploaded files.
+
+use actix_multipart::Multipart;
+use actix_web::error::{ErrorBadRequest, ErrorInternalServerError, ErrorPayloadTooLarge};
+use actix_web::http::header;
+use actix_web::web::{BytesMut, Payload};
+use actix_web::HttpRequest;
+use base64::Engine;
+use futures::StreamExt;
+use log::warn;
+use tokio::io::AsyncWriteExt;
+
+use crate::cache::tempfolder;
+use crate::externalpluginservers::UploadedFile;
+
+/// Request bodies larger than this are refused with `413`. For multipart bodies, this counts all
+/// form fields and uploaded files together.
+const MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// Multipart requests uploading more files than this are refused with `413` too.
+const MAX_FILES: usize = 32;
+
+/// Everything a plugin gets to see of a request body.
+#[derive(Default)]
+pub(crate) struct IncomingBody {
+    /// The raw body. Bodies that aren't valid UTF-8 are base64 encoded, see `body_base64`.
+    pub(crate) body: Option<String>,
+    pub(crate) body_base64: bool,
+    /// Fields from `application/x-www-form-urlencoded` or `multipart/form-data` bodies.
+    pub(crate) form: Vec<(String, String)>,
+    /// Files uploaded through `multipart/form-data`, stored under `.cynthiaTemp`.
+    pub(crate) files: Vec<UploadedFile>,
+}
+
+impl IncomingBody {
+    /// Reads the body of a request. Multipart bodies are split into form fields and files, the
+    /// files being written to disk as they come in. Other bodies are kept in memory.
+    pub(crate) async fn read(
+        req: &HttpRequest,
+        mut payload: Payload,
+    ) -> Result<Self, actix_web::Error> {
+        let content_type = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|c| c.to_str().ok())
+            .unwrap_or("")
+            .to_lowercase();
+        if content_type.starts_with("multipart/form-data") {
+            let multipart = Multipart::new(req.headers(), payload);
+            return Self::read_multipart(multipart).await;
+        }
+        let mut bytes = BytesMut::new();
+        while let Some(chunk) = payload.next().await {
+            let chunk = chunk?;
+            if bytes.len() + chunk.len() > MAX_BODY_SIZE {
+                return Err(ErrorPayloadTooLarge("Request body too large."));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        if bytes.is_empty() {
+            return Ok(IncomingBody::default());
+        }
+        let form = if content_type.starts_with("application/x-www-form-urlencoded") {
+            serde_urlencoded::from_bytes(&bytes).map_err(ErrorBadRequest)?
+        } else {
+            vec![]
+        };
+        let (body, body_base64) = match String::from_utf8(bytes.to_vec()) {
+            Ok(s) => (s, false),
+            Err(_) => (
+                base64::engine::general_purpose::STANDARD.encode(&bytes),
+                true,
+            ),
+        };
+        Ok(IncomingBody {
+            body: Some(body),
+            body_base64,
+            form,
+            files: vec![],
+        })
+    }
+
+    async fn read_multipart(mut multipart: Multipart) -> Result<Self, actix_web::Error> {
+        let mut incoming = IncomingBody::default();
+        // Bytes of fields and files read so far.
+        let mut received: usize = 0;
+        let upload_folder = tempfolder().join("uploads");
+        tokio::fs::create_dir_all(&upload_folder)
+            .await
+            .map_err(ErrorInternalServerError)?;
+        while let Some(field) = multipart.next().await {
+            let mut field = match field {
+                Ok(f) => f,
+                Err(e) => {
+                    incoming.cleanup();
+                    return Err(e.into());
+                }
+            };
+            let name = field.name().unwrap_or_default().to_string();
+            let filename = field
+                .content_disposition()
+                .and_then(|c| c.get_filename())
+                .map(String::from);
+            match filename {
+                Some(filename) => {
+                    if incoming.files.len() >= MAX_FILES {
+                        incoming.cleanup();
+                        return Err(ErrorPayloadTooLarge("Too many uploaded files."));
+                    }
+                    let path = upload_folder.join(random_string::generate(
+                        24,
+                        "abcdefghijklmnopqrstuvwxyz0123456789",
+                    ));
+                    let mut file = match tokio::fs::File::create(&path).await {
+                        Ok(file) => file,
+                        Err(e) => {
+                            incoming.cleanup();
+                            return Err(ErrorInternalServerError(e));
+                        }
+                    };
+                    // Registered before writing, so it's cleaned up on failure too.
+                    incoming.files.push(UploadedFile {
+                        field: name,
+                        filename: Some(filename),
+                        content_type: field.content_type().map(|m| m.to_string()),
+                        path: std::path::absolute(&path)
+                            .unwrap_or(path)
+                            .to_string_lossy()
+                            .to_string(),
+                        size: 0,
+                    });
+                    let upload = incoming.files.last_mut().unwrap();
+                    while let Some(chunk) = field.next().await {
+                        let written = match chunk {
+                            Ok(chunk) if received + chunk.len() > MAX_BODY_SIZE => {
+                                Err(ErrorPayloadTooLarge("Request body too large."))
+                            }
+                            Ok(chunk) => file
+                                .write_all(&chunk)
+                                .await
+                                .map(|_| chunk.len())
+                                .map_err(ErrorInternalServerError),
+                            Err(e) => Err(e.into()),
+                        };
+                        match written {
+                            Ok(n) => {
+                                received += n;
+                                upload.size += n as u64;
+                            }
+                            Err(e) => {
+                                incoming.cleanup();
+                                return Err(e);
+                            }
+                        }
+                    }
+                    // Plugins read the file once the request is passed on, it has to be complete.
+                    if let Err(e) = file.flush().await {
+                        incoming.cleanup();
+                        return Err(ErrorInternalServerError(e));
+                    }
+                }
+                None => {
+                    let mut value = BytesMut::new();
+                    while let Some(chunk) = field.next().await {
+                        let chunk = match chunk {
+                            Ok(chunk) => chunk,
+                            Err(e) => {
+                                incoming.cleanup();
+                                return Err(e.into());
+                            }
+                        };
+                        if received + chunk.len() > MAX_BODY_SIZE {
+                            incoming.cleanup();
+                            return Err(ErrorPayloadTooLarge("Request body too large."));
+                        }
+                        received += chunk.len();
+                        value.extend_from_slice(&chunk);
+                    }
+                    incoming
+                        .form
+                        .push((name, String::from_utf8_lossy(&value).to_string()));
+                }
+            }
+        }
+        Ok(incoming)
+    }
+
+    /// Removes uploaded files. Plugins should copy files they want to keep while handling the
+    /// request, as they are removed once the response is sent.
+    pub(crate) fn cleanup(&self) {
+        for file in &self.files {
+            if let Err(e) = std::fs::remove_file(&file.path) {
+                warn!("Could not remove uploaded file {}: {}", file.path, e);
+            }
+        }
+    }
+}
+
+/// The query parameters of a request, in order.
+pub(crate) fn query(req: &HttpRequest) -> Vec<(String, String)> {
+    serde_urlencoded::from_str(req.query_string()).unwrap_or_default()
+}
@@ -0,0 +1,131 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+
+//! URL normalisation. Aliases, differently cased URLs and trailing slashes all lead to a single
+//! canonical URL per publication.
+
+use actix_web::HttpRequest;
+
+use crate::config::{Site, TrailingSlash};
+use crate::publications::{url_path, CynthiaPublicationList, CynthiaPublicationListTrait};
+
+/// Returns the canonical URL for a request, if the request isn't for it already. Requests that
+/// don't lead to a publication are left alone, so they can 404 as usual.
+pub(crate) fn canonical_location(
+    req: &HttpRequest,
+    published: &CynthiaPublicationList,
+    site: &Site,
+) -> Option<String> {
+    let path = req.path();
+    let requested = path.trim_start_matches('/');
+    let bare = requested.trim_end_matches('/');
+    if bare.is_empty() {
+        return None;
+    }
+    let id = published.canonical_id(bare, site.case_insensitive)?;
+    let canonical = match url_path(&id) {
+        root if root == "/" => root,
+        p => match site.trailing_slash {
+            TrailingSlash::Strip => p,
+            TrailingSlash::Add => format!("{p}/"),
+            TrailingSlash::Ignore if requested.ends_with('/') => format!("{p}/"),
+            TrailingSlash::Ignore => p,
+        },
+    };
+    if canonical == path {
+        return None;
+    }
+    Some(match req.query_string() {
+        "" => canonical,
+        query => format!("{canonical}?{query}"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn published() -> CynthiaPublicationList {
+        serde_json::from_value(serde_json::json!([
+            {
+                "postlist": {
+                    "id": "Hello-World",
+                    "title": "Hello",
+                    "filter": "latest",
+                    "aliases": ["old/hello"],
+                }
+            },
+            { "postlist": { "id": "posts", "title": "Posts", "filter": "latest" } },
+        ]))
+        .unwrap()
+    }
+
+    fn site(trailing_slash: TrailingSlash, case_insensitive: bool) -> Site {
+        Site {
+            trailing_slash,
+            case_insensitive,
+            ..Site::default()
+        }
+    }
+
+    fn location(uri: &str, site: &Site) -> Option<String> {
+        let req = TestRequest::get().uri(uri).to_http_request();
+        canonical_location(&req, &published(), site)
+    }
+
+    #[test]
+    fn case() {
+        let insensitive = site(TrailingSlash::Strip, true);
+        assert_eq!(location("/Hello-World", &insensitive), None);
+        assert_eq!(
+            location("/hello-world", &insensitive),
+            Some(String::from("/Hello-World"))
+        );
+        assert_eq!(
+            location("/HELLO-WORLD?page=2", &insensitive),
+            Some(String::from("/Hello-World?page=2"))
+        );
+        // Without case insensitivity, other casings are left to 404.
+        assert_eq!(
+            location("/hello-world", &site(TrailingSlash::Strip, false)),
+            None
+        );
+    }
+
+    #[test]
+    fn trailing_slashes() {
+        let strip = site(TrailingSlash::Strip, false);
+        assert_eq!(location("/posts", &strip), None);
+        assert_eq!(location("/posts/", &strip), Some(String::from("/posts")));
+        let add = site(TrailingSlash::Add, false);
+        assert_eq!(location("/posts", &add), Some(String::from("/posts/")));
+        assert_eq!(location("/posts/", &add), None);
+        let ignore = site(TrailingSlash::Ignore, false);
+        assert_eq!(location("/posts", &ignore), None);
+        assert_eq!(location("/posts/", &ignore), None);
+        // The root is always `/`, and paths that aren't publications are left alone.
+        assert_eq!(location("/", &add), None);
+        assert_eq!(location("/nothing/", &strip), None);
+    }
+
+    #[test]
+    fn aliases() {
+        let strip = site(TrailingSlash::Strip, true);
+        assert_eq!(
+            location("/old/hello", &strip),
+            Some(String::from("/Hello-World"))
+        );
+        assert_eq!(
+            location("/Old/Hello/?a=b", &strip),
+            Some(String::from("/Hello-World?a=b"))
+        );
+        assert_eq!(
+            location("/old/hello/", &site(TrailingSlash::Ignore, false)),
+            Some(String::from("/Hello-World/"))
+        );
+    }
+}
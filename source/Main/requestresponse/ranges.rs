@@ -0,0 +1,322 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+
+//! Byte range requests (`Range`/`If-Range`) and streaming of files straight from disk.
+
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use actix_web::http::header::{self, ContentRangeSpec, Header, IfRange, Range};
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
+use futures::stream::{self, Stream, StreamExt};
+
+use super::conditional::Validators;
+
+/// Size of the chunks a file is streamed in.
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// The most ranges a request may ask for. Requests for more are answered with the whole resource,
+/// as splitting it up that much costs more than it saves.
+const MAX_RANGES: usize = 50;
+
+/// What part of a resource a request asks for.
+#[derive(Debug, PartialEq)]
+pub(crate) enum RangeRequest {
+    /// No (usable) `Range` header, send everything.
+    Full,
+    /// One or more inclusive byte ranges.
+    Partial(Vec<(u64, u64)>),
+    /// None of the requested ranges lie within the resource.
+    Unsatisfiable,
+}
+
+impl RangeRequest {
+    /// Reads the `Range` header of a request for a resource of `length` bytes. The header is ignored
+    /// if an `If-Range` header doesn't match the current validators.
+    pub(crate) fn from_request(req: &HttpRequest, length: u64, validators: &Validators) -> Self {
+        if !req.headers().contains_key(header::RANGE) {
+            return RangeRequest::Full;
+        }
+        if req.headers().contains_key(header::IF_RANGE) {
+            let still_valid = match IfRange::parse(req) {
+                Ok(IfRange::EntityTag(tag)) => tag.strong_eq(&validators.etag),
+                // A date only validates when it is exactly the last modification date.
+                Ok(IfRange::Date(date)) => validators
+                    .last_modified
+                    .map(|m| header::HttpDate::from(m).to_string() == date.to_string())
+                    .unwrap_or(false),
+                Err(_) => false,
+            };
+            if !still_valid {
+                return RangeRequest::Full;
+            }
+        }
+        let specs = match Range::parse(req) {
+            Ok(Range::Bytes(specs)) => specs,
+            // Unknown units and malformed headers are ignored, as RFC 9110 prescribes.
+            _ => return RangeRequest::Full,
+        };
+        if specs.len() > MAX_RANGES {
+            return RangeRequest::Full;
+        }
+        let ranges: Vec<(u64, u64)> = specs
+            .iter()
+            .filter_map(|spec| spec.to_satisfiable_range(length))
+            .collect();
+        if ranges.is_empty() {
+            RangeRequest::Unsatisfiable
+        } else {
+            RangeRequest::Partial(coalesce(ranges))
+        }
+    }
+}
+
+/// Sorts ranges and merges the ones that overlap or touch, so no byte is sent twice.
+fn coalesce(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some((_, last_end)) if start <= last_end.saturating_add(1) => {
+                *last_end = (*last_end).max(end);
+            }
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Where the bytes of a ranged response come from.
+pub(crate) enum RangeSource<'a> {
+    Memory(Vec<u8>),
+    File(&'a Path),
+}
+
+/// Builds the response for a range request. `response` should already carry any headers shared by
+/// all responses, like the validators. Full responses from a file are streamed.
+pub(crate) fn respond(
+    mut response: HttpResponseBuilder,
+    range_request: RangeRequest,
+    source: RangeSource,
+    length: u64,
+    content_type: &str,
+) -> std::io::Result<HttpResponse> {
+    response.insert_header((header::ACCEPT_RANGES, "bytes"));
+    match range_request {
+        RangeRequest::Full => {
+            response.insert_header((header::CONTENT_TYPE, content_type));
+            Ok(match source {
+                RangeSource::Memory(bytes) => response.body(bytes),
+                RangeSource::File(path) => response
+                    .no_chunking(length)
+                    .streaming(file_stream(path, 0, length)?),
+            })
+        }
+        RangeRequest::Unsatisfiable => {
+            response.status(StatusCode::RANGE_NOT_SATISFIABLE);
+            response.insert_header(header::ContentRange(ContentRangeSpec::Bytes {
+                range: None,
+                instance_length: Some(length),
+            }));
+            Ok(response.finish())
+        }
+        RangeRequest::Partial(ranges) if ranges.len() == 1 => {
+            let (start, end) = ranges[0];
+            response.status(StatusCode::PARTIAL_CONTENT);
+            response.insert_header((header::CONTENT_TYPE, content_type));
+            response.insert_header(header::ContentRange(ContentRangeSpec::Bytes {
+                range: Some((start, end)),
+                instance_length: Some(length),
+            }));
+            Ok(match source {
+                RangeSource::Memory(bytes) => {
+                    response.body(bytes[start as usize..=end as usize].to_vec())
+                }
+                RangeSource::File(path) => response
+                    .no_chunking(end - start + 1)
+                    .streaming(file_stream(path, start, end - start + 1)?),
+            })
+        }
+        RangeRequest::Partial(ranges) => {
+            let boundary = random_string::generate(24, "abcdefghijklmnopqrstuvwxyz0123456789");
+            response.status(StatusCode::PARTIAL_CONTENT);
+            response.insert_header((
+                header::CONTENT_TYPE,
+                format!("multipart/byteranges; boundary={boundary}"),
+            ));
+            let mut parts: Vec<Box<dyn Stream<Item = std::io::Result<Bytes>> + Unpin>> = vec![];
+            let mut total = 0u64;
+            for (start, end) in ranges {
+                let part_header = Bytes::from(format!(
+                    "\r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: bytes {start}-{end}/{length}\r\n\r\n"
+                ));
+                total += part_header.len() as u64 + (end - start + 1);
+                parts.push(Box::new(stream::iter([Ok(part_header)])));
+                parts.push(match &source {
+                    RangeSource::Memory(bytes) => {
+                        let part = Bytes::copy_from_slice(&bytes[start as usize..=end as usize]);
+                        Box::new(stream::iter([Ok(part)]))
+                    }
+                    RangeSource::File(path) => Box::new(file_stream(path, start, end - start + 1)?),
+                });
+            }
+            let closing = Bytes::from(format!("\r\n--{boundary}--\r\n"));
+            total += closing.len() as u64;
+            parts.push(Box::new(stream::iter([Ok(closing)])));
+            Ok(response
+                .no_chunking(total)
+                .streaming(stream::iter(parts).flatten()))
+        }
+    }
+}
+
+/// Streams `length` bytes of a file starting at `start`, in chunks, without reading the whole file
+/// into memory.
+pub(crate) fn file_stream(
+    path: &Path,
+    start: u64,
+    length: u64,
+) -> std::io::Result<impl Stream<Item = std::io::Result<Bytes>> + Unpin> {
+    let mut file = std::fs::File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    Ok(Box::pin(stream::unfold(
+        (Some(file), length),
+        |(file, remaining)| async move {
+            let mut file = file?;
+            if remaining == 0 {
+                return None;
+            }
+            let chunk_size = remaining.min(CHUNK_SIZE);
+            let result = actix_web::web::block(move || {
+                let mut buffer = vec![0; chunk_size as usize];
+                file.read_exact(&mut buffer).map(|_| (file, buffer))
+            })
+            .await;
+            // After an error the stream ends.
+            match result {
+                Ok(Ok((file, buffer))) => Some((
+                    Ok(Bytes::from(buffer)),
+                    (Some(file), remaining - chunk_size),
+                )),
+                Ok(Err(e)) => Some((Err(e), (None, 0))),
+                Err(e) => Some((Err(std::io::Error::other(e.to_string())), (None, 0))),
+            }
+        },
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn validators() -> Validators {
+        Validators::new(
+            b"body",
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+        )
+    }
+
+    fn ranges(headers: &[(&str, &str)], length: u64) -> RangeRequest {
+        let req = headers
+            .iter()
+            .fold(TestRequest::get(), |r, h| r.insert_header(*h))
+            .to_http_request();
+        RangeRequest::from_request(&req, length, &validators())
+    }
+
+    #[test]
+    fn byte_ranges() {
+        assert_eq!(ranges(&[], 100), RangeRequest::Full);
+        assert_eq!(
+            ranges(&[("Range", "bytes=0-9")], 100),
+            RangeRequest::Partial(vec![(0, 9)])
+        );
+        assert_eq!(
+            ranges(&[("Range", "bytes=90-")], 100),
+            RangeRequest::Partial(vec![(90, 99)])
+        );
+        // Ranges running past the end are cut off there.
+        assert_eq!(
+            ranges(&[("Range", "bytes=50-500")], 100),
+            RangeRequest::Partial(vec![(50, 99)])
+        );
+        assert_eq!(
+            ranges(&[("Range", "bytes=0-9, 50-59")], 100),
+            RangeRequest::Partial(vec![(0, 9), (50, 59)])
+        );
+    }
+
+    #[test]
+    fn suffix_ranges() {
+        assert_eq!(
+            ranges(&[("Range", "bytes=-10")], 100),
+            RangeRequest::Partial(vec![(90, 99)])
+        );
+        assert_eq!(
+            ranges(&[("Range", "bytes=-500")], 100),
+            RangeRequest::Partial(vec![(0, 99)])
+        );
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        assert_eq!(
+            ranges(&[("Range", "bytes=100-")], 100),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(
+            ranges(&[("Range", "bytes=200-300, 400-")], 100),
+            RangeRequest::Unsatisfiable
+        );
+        // Only the satisfiable ones are sent.
+        assert_eq!(
+            ranges(&[("Range", "bytes=200-300, 0-0")], 100),
+            RangeRequest::Partial(vec![(0, 0)])
+        );
+        // Headers that can't be read, or use other units, are ignored.
+        assert_eq!(ranges(&[("Range", "bytes=x-y")], 100), RangeRequest::Full);
+        assert_eq!(ranges(&[("Range", "lines=1-2")], 100), RangeRequest::Full);
+    }
+
+    #[test]
+    fn coalesced_ranges() {
+        assert_eq!(
+            ranges(&[("Range", "bytes=50-59, 0-9, 5-19, 20-29")], 100),
+            RangeRequest::Partial(vec![(0, 29), (50, 59)])
+        );
+        assert_eq!(
+            ranges(&[("Range", "bytes=0-99, -10")], 100),
+            RangeRequest::Partial(vec![(0, 99)])
+        );
+        let many = (0..=MAX_RANGES)
+            .map(|n| format!("{}-{}", n * 2, n * 2))
+            .collect::<Vec<String>>()
+            .join(",");
+        assert_eq!(
+            ranges(&[("Range", &format!("bytes={many}"))], 1000),
+            RangeRequest::Full
+        );
+    }
+
+    #[test]
+    fn if_range() {
+        let tag = format!("\"{}\"", validators().etag.tag());
+        let date = header::HttpDate::from(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let earlier = header::HttpDate::from(UNIX_EPOCH + Duration::from_secs(1_600_000_000));
+        let partial = RangeRequest::Partial(vec![(0, 9)]);
+        let with = |if_range: &str| ranges(&[("Range", "bytes=0-9"), ("If-Range", if_range)], 100);
+        assert_eq!(with(&tag), partial);
+        assert_eq!(with(&date.to_string()), partial);
+        assert_eq!(with("\"other\""), RangeRequest::Full);
+        assert_eq!(with(&earlier.to_string()), RangeRequest::Full);
+        // `If-Range` needs the strong comparison, weak tags never match.
+        assert_eq!(with(&format!("W/{tag}")), RangeRequest::Full);
+    }
+}
@@ -0,0 +1,330 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+
+//! Full-text search over posts.
+//!
+//! Titles, descriptions, tags, categories and the rendered content of every post go into an
+//! in-memory inverted index, which is ranked with BM25. The index is built when Cynthia starts and
+//! rebuilt on the first search after the list of posts changes.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use actix_web::web::Data;
+use log::{debug, warn};
+use tokio::sync::Mutex;
+
+use crate::publications::{
+    CynthiaPostList, CynthiaPublicationList, CynthiaPublicationListTrait, PostPublication,
+};
+use crate::{renders, LockCallback, ServerContext};
+
+/// BM25 tuning: how quickly repeated terms stop adding to the score.
+const K1: f32 = 1.2;
+/// BM25 tuning: how much longer posts are penalised.
+const B: f32 = 0.75;
+
+/// Words too common to be worth indexing.
+const STOPWORDS: [&str; 28] = [
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "from", "has", "have", "in",
+    "is", "it", "its", "not", "of", "on", "or", "that", "the", "this", "to", "was", "were", "with",
+];
+
+/// How much a term counts for, depending on where in a post it was found.
+const TITLE_WEIGHT: f32 = 3.0;
+const SHORT_WEIGHT: f32 = 2.0;
+const TAG_WEIGHT: f32 = 2.0;
+const CONTENT_WEIGHT: f32 = 1.0;
+
+/// How many results `/search.json` returns without a `limit`, and the most it returns with one.
+pub(crate) const JSON_LIMIT: usize = 20;
+pub(crate) const JSON_MAX_LIMIT: usize = 100;
+
+#[derive(Default, Debug)]
+pub(crate) struct SearchIndex {
+    /// A hash of the posts this index was built from, `None` until it is first built.
+    fingerprint: Option<u64>,
+    /// Indexed post ids, with the weighted number of terms in each.
+    documents: Vec<(String, f32)>,
+    /// For every (stemmed) term, the documents it occurs in and its weighted frequency there.
+    postings: HashMap<String, Vec<(usize, f32)>>,
+}
+
+impl SearchIndex {
+    async fn build(posts: &CynthiaPostList, fingerprint: u64) -> Self {
+        let mut index = SearchIndex {
+            fingerprint: Some(fingerprint),
+            ..Default::default()
+        };
+        for post in posts {
+            let mut terms: HashMap<String, f32> = HashMap::new();
+            let mut add = |text: &str, weight: f32| {
+                for term in tokenize(text) {
+                    *terms.entry(term).or_default() += weight;
+                }
+            };
+            add(&post.title, TITLE_WEIGHT);
+            if let Some(short) = &post.short {
+                add(short, SHORT_WEIGHT);
+            }
+            for tag in &post.tags {
+                add(tag, TAG_WEIGHT);
+            }
+            if let Some(category) = &post.category {
+                add(category, TAG_WEIGHT);
+            }
+            match renders::render_content(post.postcontent.clone()).await {
+                Some(html) => add(&strip_html(&html), CONTENT_WEIGHT),
+                None => warn!(
+                    "Could not render the content of `{}`, only its title and description are searchable.",
+                    post.id
+                ),
+            }
+            let document = index.documents.len();
+            index
+                .documents
+                .push((post.id.clone(), terms.values().sum()));
+            for (term, frequency) in terms {
+                index
+                    .postings
+                    .entry(term)
+                    .or_default()
+                    .push((document, frequency));
+            }
+        }
+        debug!(
+            "Indexed {} posts, {} terms.",
+            index.documents.len(),
+            index.postings.len()
+        );
+        index
+    }
+
+    /// Ranks the indexed posts against a query, best match first. Posts that match none of the
+    /// query terms are left out. The last term also matches longer terms it is the start of, so
+    /// results show up while the query is still being typed.
+    fn query(&self, query: &str) -> Vec<(String, f32)> {
+        let terms = tokenize(query);
+        if terms.is_empty() || self.documents.is_empty() {
+            return vec![];
+        }
+        let count = self.documents.len() as f32;
+        let average_length = self.documents.iter().map(|(_, l)| l).sum::<f32>() / count;
+        let mut scores: HashMap<usize, f32> = HashMap::new();
+        for (i, term) in terms.iter().enumerate() {
+            let mut matched: Vec<&Vec<(usize, f32)>> = vec![];
+            if let Some(postings) = self.postings.get(term) {
+                matched.push(postings);
+            }
+            if i == terms.len() - 1 && term.chars().count() >= 3 {
+                matched.extend(
+                    self.postings
+                        .iter()
+                        .filter(|(t, _)| *t != term && t.starts_with(term.as_str()))
+                        .map(|(_, p)| p),
+                );
+            }
+            for postings in matched {
+                let n = postings.len() as f32;
+                let idf = (1.0 + (count - n + 0.5) / (n + 0.5)).ln();
+                for (document, frequency) in postings {
+                    let length = self.documents[*document].1;
+                    let score = idf * frequency * (K1 + 1.0)
+                        / (frequency + K1 * (1.0 - B + B * length / average_length));
+                    *scores.entry(*document).or_default() += score;
+                }
+            }
+        }
+        let mut ranked: Vec<(String, f32)> = scores
+            .into_iter()
+            .map(|(document, score)| (self.documents[document].0.clone(), score))
+            .collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        ranked
+    }
+}
+
+/// Builds the index, if it isn't built from the current posts already.
+async fn refresh(server_context_mutex: &Data<Arc<Mutex<ServerContext>>>, posts: &CynthiaPostList) {
+    let fingerprint = {
+        let mut hasher = DefaultHasher::new();
+        serde_json::to_string(posts)
+            .unwrap_or_default()
+            .hash(&mut hasher);
+        hasher.finish()
+    };
+    let current = server_context_mutex
+        .lock_callback(|a| a.search_index.fingerprint == Some(fingerprint))
+        .await;
+    if current {
+        return;
+    }
+    // Built without holding the lock, as rendering content can take a while.
+    let index = SearchIndex::build(posts, fingerprint).await;
+    server_context_mutex
+        .lock_callback(|a| a.search_index = index)
+        .await;
+}
+
+/// Searches the posts in `published`, best match first, with their scores.
+pub(crate) async fn search(
+    server_context_mutex: &Data<Arc<Mutex<ServerContext>>>,
+    published: &CynthiaPublicationList,
+    query: &str,
+) -> Vec<(PostPublication, f32)> {
+    let posts = published.only_posts();
+    refresh(server_context_mutex, &posts).await;
+    server_context_mutex
+        .lock_callback(|a| a.search_index.query(query))
+        .await
+        .into_iter()
+        .filter_map(|(id, score)| {
+            posts
+                .iter()
+                .find(|p| p.id == id)
+                .map(|p| (p.clone(), score))
+        })
+        .collect()
+}
+
+/// Builds the index when Cynthia starts, so the first search doesn't have to wait for it.
+pub(crate) async fn indexer(server_context_mutex: Arc<Mutex<ServerContext>>) {
+    let server_context_mutex = Data::new(server_context_mutex);
+    let published = CynthiaPublicationList::load(server_context_mutex.clone()).await;
+    refresh(&server_context_mutex, &published.only_posts()).await;
+}
+
+/// Splits text into lowercase, stemmed terms, leaving out stopwords.
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .filter(|w| !STOPWORDS.contains(&w.as_str()))
+        .map(|w| stem(&w))
+        .collect()
+}
+
+/// A light stemmer for English, after the first step of Porter's algorithm: it strips plurals and
+/// `-ed`, `-ing` and `-ly` endings, so "posts", "posted" and "posting" all become "post". Words
+/// with other than ASCII letters are left as they are.
+fn stem(word: &str) -> String {
+    if word.len() <= 3 || !word.bytes().all(|b| b.is_ascii_lowercase()) {
+        return word.to_string();
+    }
+    let has_vowel = |s: &str| s.bytes().any(|b| b"aeiouy".contains(&b));
+    let mut w = word.to_string();
+    if w.ends_with("sses") || w.ends_with("ies") {
+        w.truncate(w.len() - 2);
+        if w.ends_with('i') {
+            w.pop();
+            w.push('y');
+        }
+    } else if w.ends_with('s') && !w.ends_with("ss") && !w.ends_with("us") && !w.ends_with("is") {
+        w.pop();
+    }
+    if let Some(base) = w
+        .strip_suffix("ly")
+        .filter(|b| b.len() >= 3 && has_vowel(b))
+    {
+        w = base.to_string();
+    }
+    // "need" keeps its ending.
+    let stripped = ["ing", "ed"]
+        .iter()
+        .filter(|_| !w.ends_with("eed"))
+        .find_map(|s| w.strip_suffix(s))
+        .filter(|b| b.len() >= 2 && has_vowel(b))
+        .map(String::from);
+    if let Some(mut base) = stripped {
+        let bytes = base.as_bytes();
+        let last = bytes[bytes.len() - 1];
+        if base.ends_with("at") || base.ends_with("bl") || base.ends_with("iz") {
+            // "related" becomes "relate", not "relat".
+            base.push('e');
+        } else if bytes.len() >= 2 && last == bytes[bytes.len() - 2] && !b"aeioulsz".contains(&last)
+        {
+            // "running" becomes "run", not "runn".
+            base.pop();
+        }
+        w = base;
+    }
+    w
+}
+
+/// Reduces HTML to its text, dropping tags and the contents of `<script>` and `<style>`.
+fn strip_html(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        text.push(' ');
+        let tag = &rest[start..];
+        let Some(end) = tag.find('>') else {
+            rest = "";
+            break;
+        };
+        let name = tag[1..end]
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or("")
+            .to_lowercase();
+        rest = &tag[end + 1..];
+        if name == "script" || name == "style" {
+            let closing = format!("</{}", name);
+            rest = match rest
+                .as_bytes()
+                .windows(closing.len())
+                .position(|w| w.eq_ignore_ascii_case(closing.as_bytes()))
+            {
+                Some(i) => &rest[i..],
+                None => "",
+            };
+        }
+    }
+    text.push_str(rest);
+    text.replace("&amp;", "&")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stemming() {
+        for (word, stemmed) in [
+            ("posts", "post"),
+            ("posted", "post"),
+            ("posting", "post"),
+            ("running", "run"),
+            ("related", "relate"),
+            ("stories", "story"),
+            ("classes", "class"),
+            ("quickly", "quick"),
+            ("status", "status"),
+            ("need", "need"),
+            ("rust", "rust"),
+        ] {
+            assert_eq!(stem(word), stemmed, "stemming `{word}`");
+        }
+    }
+
+    #[test]
+    fn html_to_text() {
+        assert_eq!(
+            tokenize(&strip_html(
+                "<p>Hello <b>world</b>!</p><script>let hidden = 1;</script><style>p {}</style>"
+            )),
+            vec!["hello", "world"]
+        );
+    }
+}
@@ -0,0 +1,155 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+
+//! `/sitemap.xml`, listing the publications search engines should know about, and `/robots.txt`,
+//! pointing crawlers to it.
+//!
+//! A sitemap holds at most 50,000 URLs. Sites with more publications than that get a sitemap index
+//! on `/sitemap.xml` instead, which points to `/sitemap-1.xml`, `/sitemap-2.xml` and so on.
+
+use std::sync::Arc;
+
+use actix_web::web::Data;
+use chrono::{DateTime, SecondsFormat};
+use tokio::sync::Mutex;
+
+use crate::config::{CynthiaConfClone, TrailingSlash};
+use crate::feeds::escape;
+use crate::publications::{
+    is_noindex, robots_directives, url_path, CynthiaPublication, CynthiaPublicationList,
+    CynthiaPublicationListTrait,
+};
+use crate::{renders, LockCallback, ServerContext};
+
+/// The most URLs a single sitemap may hold.
+const SITEMAP_LIMIT: usize = 50_000;
+
+struct SitemapUrl {
+    loc: String,
+    lastmod: Option<u64>,
+    priority: Option<f32>,
+}
+
+/// Renders `/sitemap.xml` when `part` is `None`, or `/sitemap-{part}.xml`. Returns `None` if the
+/// sitemap is disabled, or there is no such part.
+pub(crate) async fn render_sitemap(
+    server_context_mutex: &Data<Arc<Mutex<ServerContext>>>,
+    part: Option<usize>,
+) -> Option<String> {
+    let config = server_context_mutex
+        .lock_callback(|a| a.config.clone())
+        .await;
+    if !config.site.meta.enable_sitemap {
+        return None;
+    }
+    let base = config.site.site_baseurl.trim_end_matches('/');
+    let published = CynthiaPublicationList::load(server_context_mutex.clone()).await;
+    let mut urls: Vec<SitemapUrl> = vec![];
+    for publication in published.iter() {
+        // Publications search engines shouldn't index, like the 404 page, are left out.
+        if !publication.get_options().is_some_and(|o| o.sitemap)
+            || is_noindex(&robots_directives(publication, &published, &config))
+        {
+            continue;
+        }
+        // Sitemaps should only hold canonical URLs.
+        let loc = match url_path(&publication.get_id()) {
+            root if root == "/" => root,
+            p if config.site.trailing_slash == TrailingSlash::Add => format!("{p}/"),
+            p => p,
+        };
+        // A postlist changes whenever one of its posts does.
+        let lastmod = match publication {
+            CynthiaPublication::PostList { filter, .. } => {
+                renders::list_posts(filter.clone(), &published, server_context_mutex)
+                    .await
+                    .iter()
+                    .map(|p| p.dates.altered)
+                    .max()
+            }
+            _ => publication.get_dates().map(|d| d.altered),
+        };
+        urls.push(SitemapUrl {
+            loc: format!("{}{}", base, loc),
+            lastmod: lastmod.filter(|d| *d != 0),
+            priority: publication.get_options().and_then(|o| o.priority),
+        });
+    }
+    let chunks: Vec<&[SitemapUrl]> = urls.chunks(SITEMAP_LIMIT).collect();
+    match part {
+        None if chunks.len() <= 1 => Some(urlset(&urls)),
+        None => Some(index(base, chunks.len())),
+        Some(n) if chunks.len() > 1 && n >= 1 => chunks.get(n - 1).map(|c| urlset(c)),
+        Some(_) => None,
+    }
+}
+
+/// Renders `/robots.txt`, or returns `None` if it is disabled. It only links to the sitemap when
+/// that is enabled and `site_baseurl` is set, as crawlers need an absolute URL for it.
+pub(crate) fn render_robots_txt(config: &CynthiaConfClone) -> Option<String> {
+    let robots = &config.site.robots;
+    if !robots.robots_txt {
+        return None;
+    }
+    let mut txt = String::from("User-agent: *\n");
+    if robots.disallow.is_empty() {
+        txt.push_str("Disallow:\n");
+    }
+    for path in &robots.disallow {
+        txt.push_str(&format!("Disallow: {}\n", path));
+    }
+    let base = config.site.site_baseurl.trim_end_matches('/');
+    if config.site.meta.enable_sitemap && !base.is_empty() {
+        txt.push_str(&format!("\nSitemap: {}/sitemap.xml\n", base));
+    }
+    Some(txt)
+}
+
+/// Parses the part number out of `sitemap-{n}.xml`.
+pub(crate) fn split_part(file_name: &str) -> Option<usize> {
+    file_name
+        .strip_prefix("sitemap-")?
+        .strip_suffix(".xml")
+        .filter(|n| !n.starts_with('0'))?
+        .parse()
+        .ok()
+}
+
+fn urlset(urls: &[SitemapUrl]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+    for url in urls {
+        xml.push_str(&format!("<url><loc>{}</loc>", escape(&url.loc)));
+        if let Some(lastmod) = url.lastmod {
+            xml.push_str(&format!("<lastmod>{}</lastmod>", w3c_date(lastmod)));
+        }
+        if let Some(priority) = url.priority {
+            xml.push_str(&format!("<priority>{}</priority>", priority));
+        }
+        xml.push_str("</url>\n");
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+fn index(base: &str, parts: usize) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+    for n in 1..=parts {
+        xml.push_str(&format!(
+            "<sitemap><loc>{}</loc></sitemap>\n",
+            escape(&format!("{}/sitemap-{}.xml", base, n))
+        ));
+    }
+    xml.push_str("</sitemapindex>\n");
+    xml
+}
+
+fn w3c_date(unix: u64) -> String {
+    DateTime::from_timestamp(unix as i64, 0)
+        .unwrap_or_default()
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
@@ -0,0 +1,165 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+
+//! TLS termination using rustls.
+//!
+//! The certificate is handed to rustls through a resolver that can be swapped out at runtime, so
+//! renewed certificates are picked up without restarting Cynthia.
+
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use actix_web::http::header;
+use actix_web::web::Data;
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use log::{error, info, warn};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+
+use crate::config::Tls;
+
+/// Hands out the most recently loaded certificate to rustls.
+#[derive(Debug)]
+pub(crate) struct ReloadingCertResolver {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        self.current.read().ok().map(|key| key.clone())
+    }
+}
+
+/// Reads the certificate chain and private key from the paths in the TLS config.
+fn load_certified_key(tls: &Tls) -> Result<CertifiedKey, String> {
+    let cert_file =
+        File::open(&tls.cert).map_err(|e| format!("Could not open `{}`: {e}", tls.cert))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(cert_file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Could not read certificates from `{}`: {e}", tls.cert))?;
+    if certs.is_empty() {
+        return Err(format!("No certificates found in `{}`.", tls.cert));
+    }
+    let key_file =
+        File::open(&tls.key).map_err(|e| format!("Could not open `{}`: {e}", tls.key))?;
+    let key = match rustls_pemfile::private_key(&mut BufReader::new(key_file)) {
+        Ok(Some(key)) => key,
+        Ok(None) => return Err(format!("No private key found in `{}`.", tls.key)),
+        Err(e) => {
+            return Err(format!(
+                "Could not read private key from `{}`: {e}",
+                tls.key
+            ))
+        }
+    };
+    let signing_key = rustls::crypto::ring::sign::any_supported_type(&key)
+        .map_err(|e| format!("Unsupported private key in `{}`: {e}", tls.key))?;
+    Ok(CertifiedKey::new(certs, signing_key))
+}
+
+/// Builds the rustls server config, returning the resolver too so it can be handed to [`watch`].
+pub(crate) fn server_config(
+    tls: &Tls,
+) -> Result<(ServerConfig, Arc<ReloadingCertResolver>), String> {
+    let resolver = Arc::new(ReloadingCertResolver {
+        current: RwLock::new(Arc::new(load_certified_key(tls)?)),
+    });
+    let mut config =
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?
+            .with_no_client_auth()
+            .with_cert_resolver(resolver.clone());
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok((config, resolver))
+}
+
+fn modified(tls: &Tls) -> (Option<SystemTime>, Option<SystemTime>) {
+    let m = |p: &str| fs::metadata(p).and_then(|m| m.modified()).ok();
+    (m(&tls.cert), m(&tls.key))
+}
+
+/// Polls the certificate and key files, and swaps in the new certificate when either one changes.
+/// If the new files can't be loaded, the previous certificate stays in use.
+pub(crate) async fn watch(resolver: Option<Arc<ReloadingCertResolver>>, tls: Option<Tls>) {
+    let (Some(resolver), Some(tls)) = (resolver, tls) else {
+        return;
+    };
+    let mut last_modified = modified(&tls);
+    let mut interval = tokio::time::interval(Duration::from_secs(tls.reload_interval.max(1)));
+    loop {
+        interval.tick().await;
+        let now_modified = modified(&tls);
+        if now_modified == last_modified {
+            continue;
+        }
+        last_modified = now_modified;
+        match load_certified_key(&tls) {
+            Ok(key) => {
+                if let Ok(mut current) = resolver.current.write() {
+                    *current = Arc::new(key);
+                    info!("Reloaded TLS certificate from `{}`.", tls.cert);
+                }
+            }
+            Err(e) => warn!("Could not reload TLS certificate, keeping the old one: {e}"),
+        }
+    }
+}
+
+/// The port HTTPS is served on, used to build redirect locations.
+struct HttpsPort(u16);
+
+async fn redirect_to_https(req: HttpRequest, https_port: Data<HttpsPort>) -> HttpResponse {
+    let connection_info = req.connection_info();
+    let host = connection_info.host();
+    // Strip the port, taking care not to cut into an IPv6 address.
+    let host = match host.rsplit_once(':') {
+        Some((h, p)) if !p.contains(']') => h,
+        _ => host,
+    };
+    let port = match https_port.0 {
+        443 => String::new(),
+        p => format!(":{p}"),
+    };
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or("/");
+    HttpResponse::MovedPermanently()
+        .insert_header((header::LOCATION, format!("https://{host}{port}{path}")))
+        .finish()
+}
+
+/// Runs the plain HTTP listener that redirects to HTTPS, if one is configured.
+pub(crate) async fn redirect_server(tls: Option<Tls>, https_port: u16) {
+    let Some(address) = tls.and_then(|t| t.redirect) else {
+        return;
+    };
+    let https_port = Data::new(HttpsPort(https_port));
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(https_port.clone())
+            .default_service(web::to(redirect_to_https))
+    })
+    .workers(1)
+    .bind(&address);
+    match server {
+        Ok(server) => {
+            println!("Redirecting http://{} to HTTPS", address);
+            if let Err(e) = server.run().await {
+                error!("HTTPS redirect listener stopped: {e}");
+            }
+        }
+        Err(e) => {
+            error!("Could not bind to {}, error message: {}", address, e);
+            std::process::exit(1);
+        }
+    }
+}
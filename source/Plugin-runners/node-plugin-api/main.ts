@@ -47,6 +47,13 @@ export interface ContentRenderRequestBody {
   template_data: {
     meta: ContentMetaDataType;
     content: string;
+    query: Record<string, string>;
+    archive: Array<{
+      year: number;
+      month: number;
+      count: number;
+      url: string;
+    }>;
   };
 }
 export interface ContentMetaDataType {
@@ -115,6 +122,26 @@ export interface PostlistRenderRequestBody {
       };
       scene_override: string;
     }>;
+    query: Record<string, string>;
+    archive: Array<{
+      year: number;
+      month: number;
+      count: number;
+      url: string;
+    }>;
+    pagination: {
+      current: number;
+      total: number;
+      per_page: number;
+      posts: number;
+      previous?: string;
+      next?: string;
+    };
+    feeds: {
+      rss?: string;
+      atom?: string;
+      json?: string;
+    };
   };
 }
 
@@ -160,6 +187,12 @@ export interface WebResponse {
     as: "WebResponse";
     append_headers: Array<[string, string]>;
     response_body: string;
+    // Defaults to 200.
+    status?: number;
+    // Set when `response_body` is base64 encoded binary data.
+    body_base64?: boolean;
+    // A file Cynthia streams as the body instead of `response_body`.
+    body_file?: string;
   };
 }
 
@@ -220,7 +253,12 @@ export const Cynthia = {
 };
 export interface ResponderResponse {
   headers: Array<[string, string]>;
-  body: string;
+  // Binary bodies (like images) can be given as a Buffer or Uint8Array.
+  body: string | Uint8Array;
+  // The HTTP status code, defaults to 200.
+  status?: number;
+  // Path to a file to send as the body instead, Cynthia streams it from disk. Use this for large files.
+  file?: string;
 }
 export type Responder = () => ResponderResponse | string;
 /*
@@ -254,11 +292,34 @@ export interface IncomingWebRequest {
     method: string;
     uri: string;
     headers: Array<[string, string]>;
+    query: Array<[string, string]>;
+    body: string | null;
+    body_base64: boolean;
+    form: Array<[string, string]>;
+    files: UploadedFile[];
   };
 }
+// A file uploaded in a multipart form. It is removed once the request is answered, so copy it from `path` if you want to keep it.
+export interface UploadedFile {
+  field: string;
+  filename: string | null;
+  content_type: string | null;
+  path: string;
+  size: number;
+}
+type WebRequestData = {
+  method: string;
+  uri: string;
+  headers: Array<[string, string]>;
+  query?: Array<[string, string]>;
+  body?: string | null;
+  body_base64?: boolean;
+  form?: Array<[string, string]>;
+  files?: UploadedFile[];
+};
 export class WebRequest {
   // Method, URI, and headers are immutable
-  // Metod is either GET or POST, no need to check for other methods, so it is fine being protected.
+  // Method can be any HTTP method, plugins check it through the `get`, `post`, `put` (etc.) and `on` methods, so it is fine being protected.
   protected readonly method: string;
   // URI is the path of the request, this might be matched with a regex, making it possible for
   // plugins to match multiple paths with one rule. It should as such not be protected.
@@ -266,24 +327,46 @@ export class WebRequest {
   // Headers are the headers of the request, they are used to check for the presence of a header.
   // They can be read by the plugin here, or using the header method.
   readonly headers: Array<[string, string]>;
+  // Query parameters, in the order they appear in the URI.
+  readonly query: Array<[string, string]>;
+  // The raw request body, if any. When `bodyBase64` is true, the body wasn't valid UTF-8 and is base64 encoded.
+  readonly body: string | null;
+  readonly bodyBase64: boolean;
+  // Fields of a urlencoded or multipart form body.
+  readonly form: Array<[string, string]>;
+  // Files uploaded through a multipart form.
+  readonly files: UploadedFile[];
   // ID is the id of the request, it is used to identify the request in the response. It is immutable, and irrelevant to the plugin.
   private readonly id: number;
   // Once a request is claimed, it cannot be claimed again. This is how multiple plugins responding to the same request is handled.
   protected claimed: boolean;
   constructor(
       id: number,
-      a: { method: string; uri: string; headers: Array<[string, string]> },
+      a: WebRequestData,
   ) {
     this.id = id;
     this.method = a.method;
     this.uri = a.uri;
     this.headers = a.headers;
+    this.query = a.query ?? [];
+    this.body = a.body ?? null;
+    this.bodyBase64 = a.body_base64 ?? false;
+    this.form = a.form ?? [];
+    this.files = a.files ?? [];
     this.claimed = false;
   }
   // This method is used to get a header from the headers array. It returns the value of the header, or undefined if the header is not present.
   header(name: string) {
     return this.headers.find((header) => header[0] === name)?.[1];
   }
+  // Returns the value of a query parameter, or undefined if it is not present.
+  param(name: string) {
+    return this.query.find((param) => param[0] === name)?.[1];
+  }
+  // Returns the value of a form field, or undefined if it is not present.
+  field(name: string) {
+    return this.form.find((field) => field[0] === name)?.[1];
+  }
   protected respond(responder: Responder) {
     const responder_answ = (() => {
       const res = responder();
@@ -295,12 +378,19 @@ export class WebRequest {
       }
       return res;
     })();
+    const body = responder_answ.body;
     const response: WebResponse = {
       id: this.id,
       body: {
         as: "WebResponse",
         append_headers: responder_answ.headers,
-        response_body: responder_answ.body,
+        response_body:
+            typeof body === "string"
+                ? body
+                : Buffer.from(body).toString("base64"),
+        status: responder_answ.status,
+        body_base64: typeof body !== "string",
+        body_file: responder_answ.file,
       },
     };
     Cynthia.send(response);
@@ -316,26 +406,37 @@ export class WebRequest {
         "^" + rule.split("*").map(escapeRegex).join(".*") + "$",
     ).test(str);
   }
-  // Respond to a GET request, if the URI matches the request URI (wildcards supported), then the response in the callback is send back.
-  get(adress: string, responder: Responder) {
+  // Respond to a request with the given method, if the URI matches the request URI (wildcards supported), then the response in the callback is send back.
+  on(method: string, adress: string, responder: Responder) {
     if (
         this.matchUris(this.uri, adress) &&
-        this.method.toUpperCase() === "GET" &&
+        this.method.toUpperCase() === method.toUpperCase() &&
         !this.claimed
     ) {
       this.claimed = true;
       this.respond(responder);
     }
   }
+  // Respond to a GET request. HEAD requests are answered by the same responder, Cynthia leaves out the body.
+  get(adress: string, responder: Responder) {
+    this.on("GET", adress, responder);
+    this.on("HEAD", adress, responder);
+  }
   post(adress: string, responder: Responder) {
-    if (
-        this.matchUris(this.uri, adress) &&
-        this.method.toUpperCase() === "POST" &&
-        !this.claimed
-    ) {
-      this.claimed = true;
-      this.respond(responder);
-    }
+    this.on("POST", adress, responder);
+  }
+  put(adress: string, responder: Responder) {
+    this.on("PUT", adress, responder);
+  }
+  patch(adress: string, responder: Responder) {
+    this.on("PATCH", adress, responder);
+  }
+  delete(adress: string, responder: Responder) {
+    this.on("DELETE", adress, responder);
+  }
+  // Respond to an OPTIONS request, for example a CORS preflight. Unclaimed OPTIONS requests get an `Allow` header from Cynthia.
+  options(adress: string, responder: Responder) {
+    this.on("OPTIONS", adress, responder);
   }
   escalate() {
     if (!this.claimed) {
@@ -52,6 +52,11 @@ export default async function handle(
           method: request.body.method,
           uri: request.body.uri,
           headers: request.body.headers,
+          query: request.body.query,
+          body: request.body.body,
+          body_base64: request.body.body_base64,
+          form: request.body.form,
+          files: request.body.files,
         });
         for (const modifier of cynthiabase.modifyRequest) {
           modifier(req, CynthiaPassed);
@@ -21,12 +21,31 @@ export default interface Config {
     og_sitename: string;
     meta: {
       enable_tags: boolean;
+      enable_rss: boolean;
+      enable_atom: boolean;
+      enable_json_feed: boolean;
+      feed_content: boolean;
+      enable_sitemap: boolean;
+      enable_api: boolean;
+    };
+    trailing_slash: "strip" | "add" | "ignore";
+    case_insensitive: boolean;
+    per_page: number;
+    timezone: string;
+    robots: {
+      robots_txt: boolean;
+      disallow: Array<string>;
+      directives: string;
     };
   };
   logs: {
     file_loglevel: number;
     term_loglevel: number;
     logfile: string;
+    access_log?: {
+      path: string;
+      format: "combined" | "json";
+    };
   };
   runtimes: {
     ext_js_rt: string;